@@ -0,0 +1,364 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{JsonRecord, TestOutcome};
+
+#[derive(Debug, Error)]
+pub enum ResultsError {
+    #[error("I/O Error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Line {line}: {source}")]
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+}
+
+/// Reads a file written by [`crate::JsonResultWriter`].
+///
+/// # Errors
+///
+/// Will return [`ResultsError`] if the file can't be read or one of its
+/// lines isn't a valid record.
+pub fn read_json_results(path: &Path) -> Result<Vec<JsonRecord>, ResultsError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        records.push(
+            serde_json::from_str(&line).map_err(|source| ResultsError::Parse {
+                line: idx + 1,
+                source,
+            })?,
+        );
+    }
+
+    Ok(records)
+}
+
+fn is_failure(outcome: TestOutcome) -> bool {
+    matches!(
+        outcome,
+        TestOutcome::Fail | TestOutcome::Crash | TestOutcome::Timeout
+    )
+}
+
+fn is_pass(outcome: TestOutcome) -> bool {
+    matches!(outcome, TestOutcome::Pass | TestOutcome::Warn)
+}
+
+/// A test whose outcome changed between two runs
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutcomeChange {
+    pub name: String,
+    pub old: TestOutcome,
+    pub new: TestOutcome,
+}
+
+/// Differences between two runs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comparison {
+    /// Tests that were passing or skipped, and now fail
+    pub new_failures: Vec<OutcomeChange>,
+
+    /// Tests that were failing, and now pass
+    pub fixes: Vec<OutcomeChange>,
+
+    /// Tests that used to run, and are now skipped
+    pub new_skips: Vec<OutcomeChange>,
+
+    /// Tests only found in the new run
+    pub appeared: Vec<String>,
+
+    /// Tests only found in the old run
+    pub disappeared: Vec<String>,
+}
+
+impl Comparison {
+    #[must_use]
+    pub fn is_regression(&self) -> bool {
+        !self.new_failures.is_empty()
+    }
+}
+
+// The same test run against two devices has two distinct results. Device
+// nodes can be numbered differently from one boot to the next, so devices are
+// told apart by their driver, and their index among the devices using it in
+// the order they show up in, e.g. `suite::test@vkms#1`.
+fn outcomes(records: &[JsonRecord]) -> BTreeMap<String, TestOutcome> {
+    let mut devices: Vec<(&str, &str)> = Vec::new();
+
+    records
+        .iter()
+        .map(|record| {
+            let name = format!("{}::{}", record.suite, record.test);
+
+            let Some(ref device) = record.device else {
+                return (name, record.outcome);
+            };
+
+            let driver = record.driver.as_deref().unwrap_or("unknown");
+
+            if !devices.iter().any(|&(known, _)| known == device) {
+                devices.push((device, driver));
+            }
+
+            let index = devices
+                .iter()
+                .filter(|&&(_, known)| known == driver)
+                .position(|&(known, _)| known == device)
+                .unwrap_or_default();
+
+            (format!("{name}@{driver}#{index}"), record.outcome)
+        })
+        .collect()
+}
+
+/// Compares the results of two runs, device by device. If a test shows up
+/// several times for the same device in a run, its last result is used.
+#[must_use]
+pub fn compare_results(old: &[JsonRecord], new: &[JsonRecord]) -> Comparison {
+    let old = outcomes(old);
+    let new = outcomes(new);
+    let mut comparison = Comparison::default();
+
+    for (name, &old_outcome) in &old {
+        let Some(&new_outcome) = new.get(name) else {
+            comparison.disappeared.push(name.clone());
+            continue;
+        };
+
+        let change = OutcomeChange {
+            name: name.clone(),
+            old: old_outcome,
+            new: new_outcome,
+        };
+
+        if is_failure(new_outcome) && !is_failure(old_outcome) {
+            comparison.new_failures.push(change);
+        } else if is_pass(new_outcome) && is_failure(old_outcome) {
+            comparison.fixes.push(change);
+        } else if new_outcome == TestOutcome::Skip && old_outcome != TestOutcome::Skip {
+            comparison.new_skips.push(change);
+        }
+    }
+
+    comparison.appeared = new
+        .keys()
+        .filter(|name| !old.contains_key(*name))
+        .cloned()
+        .collect();
+
+    comparison
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let changes = [
+            ("New failures", &self.new_failures),
+            ("Fixes", &self.fixes),
+            ("New skips", &self.new_skips),
+        ];
+
+        for (title, changes) in changes {
+            writeln!(f, "{title}: {}", changes.len())?;
+
+            for change in changes {
+                writeln!(
+                    f,
+                    "    {}: {} -> {}",
+                    change.name,
+                    change.old.as_str(),
+                    change.new.as_str()
+                )?;
+            }
+        }
+
+        for (title, names) in [
+            ("Appeared", &self.appeared),
+            ("Disappeared", &self.disappeared),
+        ] {
+            writeln!(f, "{title}: {}", names.len())?;
+
+            for name in names {
+                writeln!(f, "    {name}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{tests::TempPath, JsonRecord, TestOutcome};
+
+    use super::{compare_results, read_json_results, OutcomeChange, ResultsError};
+
+    fn record(test: &str, outcome: TestOutcome) -> JsonRecord {
+        JsonRecord {
+            suite: String::from("cgt::tests::dummy"),
+            test: test.to_string(),
+            outcome,
+            error: None,
+            duration: 0.0,
+            device: None,
+            driver: None,
+            driver_version: None,
+            dmesg: Vec::new(),
+            taint: None,
+            verdict: None,
+            expectation: None,
+        }
+    }
+
+    fn change(test: &str, old: TestOutcome, new: TestOutcome) -> OutcomeChange {
+        OutcomeChange {
+            name: format!("cgt::tests::dummy::{test}"),
+            old,
+            new,
+        }
+    }
+
+    fn results_file(name: &str, content: &str) -> TempPath {
+        let path = TempPath::new(&format!("results-{name}"));
+
+        std::fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn compare() {
+        let old = [
+            record("test_stable", TestOutcome::Pass),
+            record("test_regressed", TestOutcome::Pass),
+            record("test_fixed", TestOutcome::Fail),
+            record("test_skipped", TestOutcome::Pass),
+            record("test_crash", TestOutcome::Skip),
+            record("test_still_failing", TestOutcome::Fail),
+            record("test_removed", TestOutcome::Pass),
+        ];
+        let new = [
+            record("test_stable", TestOutcome::Pass),
+            record("test_regressed", TestOutcome::Timeout),
+            record("test_fixed", TestOutcome::Pass),
+            record("test_skipped", TestOutcome::Skip),
+            record("test_crash", TestOutcome::Crash),
+            record("test_still_failing", TestOutcome::Crash),
+            record("test_added", TestOutcome::Fail),
+        ];
+
+        let comparison = compare_results(&old, &new);
+
+        assert_eq!(
+            comparison.new_failures,
+            vec![
+                change("test_crash", TestOutcome::Skip, TestOutcome::Crash),
+                change("test_regressed", TestOutcome::Pass, TestOutcome::Timeout),
+            ]
+        );
+        assert_eq!(
+            comparison.fixes,
+            vec![change("test_fixed", TestOutcome::Fail, TestOutcome::Pass)]
+        );
+        assert_eq!(
+            comparison.new_skips,
+            vec![change("test_skipped", TestOutcome::Pass, TestOutcome::Skip)]
+        );
+        assert_eq!(comparison.appeared, vec!["cgt::tests::dummy::test_added"]);
+        assert_eq!(
+            comparison.disappeared,
+            vec!["cgt::tests::dummy::test_removed"]
+        );
+        assert!(comparison.is_regression());
+    }
+
+    #[test]
+    fn several_devices() {
+        let on = |device: &str, driver: &str, outcome| JsonRecord {
+            device: Some(device.to_string()),
+            driver: Some(driver.to_string()),
+            ..record("test_regressed", outcome)
+        };
+
+        let old = [
+            on("/dev/dri/card0", "i915", TestOutcome::Pass),
+            on("/dev/dri/card1", "vkms", TestOutcome::Pass),
+            on("/dev/dri/card2", "vkms", TestOutcome::Pass),
+        ];
+        // The devices got renumbered since the last run.
+        let new = [
+            on("/dev/dri/card1", "i915", TestOutcome::Pass),
+            on("/dev/dri/card2", "vkms", TestOutcome::Pass),
+            on("/dev/dri/card0", "vkms", TestOutcome::Fail),
+        ];
+
+        let comparison = compare_results(&old, &new);
+
+        assert_eq!(
+            comparison.new_failures,
+            vec![OutcomeChange {
+                name: String::from("cgt::tests::dummy::test_regressed@vkms#1"),
+                old: TestOutcome::Pass,
+                new: TestOutcome::Fail,
+            }]
+        );
+        assert!(comparison.appeared.is_empty());
+        assert!(comparison.disappeared.is_empty());
+    }
+
+    #[test]
+    fn no_regression() {
+        let old = [
+            record("test_fixed", TestOutcome::Fail),
+            record("test_skipped", TestOutcome::Pass),
+        ];
+        let new = [
+            record("test_fixed", TestOutcome::Pass),
+            record("test_skipped", TestOutcome::Skip),
+        ];
+
+        assert!(!compare_results(&old, &new).is_regression());
+    }
+
+    #[test]
+    fn display() {
+        let old = [record("test_regressed", TestOutcome::Pass)];
+        let new = [record("test_regressed", TestOutcome::Fail)];
+
+        assert_eq!(
+            compare_results(&old, &new).to_string(),
+            "New failures: 1\n    cgt::tests::dummy::test_regressed: pass -> fail\nFixes: 0\nNew skips: 0\nAppeared: 0\nDisappeared: 0\n"
+        );
+    }
+
+    #[test]
+    fn read_results() {
+        let line = serde_json::to_string(&record("test_pass", TestOutcome::Pass)).unwrap();
+        let path = results_file("valid", &format!("{line}\n\n{line}\n"));
+
+        assert_eq!(read_json_results(&path).unwrap().len(), 2);
+
+        let path = results_file("invalid", &format!("{line}\n{{\"suite\": 42}}\n"));
+
+        assert!(matches!(
+            read_json_results(&path),
+            Err(ResultsError::Parse { line: 2, .. })
+        ));
+    }
+}
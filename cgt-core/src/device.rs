@@ -0,0 +1,768 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, ErrorKind},
+    os::fd::AsFd,
+    path::{Path, PathBuf},
+};
+
+use drm_helpers::{get_unique, get_version};
+use thiserror::Error;
+
+const DEFAULT_DEV_ROOT: &str = "/dev";
+const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceSpecifier {
+    /// First device using the given driver
+    ModuleName(String),
+
+    /// Nth device using the given driver, starting at 0
+    ModuleIndex(String, usize),
+
+    /// Device found at the given bus ID, such as `0000:03:00.0`. A `pci:` or
+    /// `platform:` prefix is ignored.
+    BusId(String),
+
+    /// Name of a link in `/dev/dri/by-path`, such as `pci-0000:03:00.0-card`
+    ByPath(String),
+
+    /// Render node of the device matched by the inner specifier
+    RenderNode(Box<DeviceSpecifier>),
+
+    Path(PathBuf),
+}
+
+/// A device looked at while searching for the one to test
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub driver: Option<String>,
+    pub bus_id: Option<String>,
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+
+        let details: Vec<&str> = [self.driver.as_deref(), self.bus_id.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn candidates_list(candidates: &[Candidate]) -> String {
+    if candidates.is_empty() {
+        return String::from("no DRM device found");
+    }
+
+    let candidates: Vec<String> = candidates.iter().map(ToString::to_string).collect();
+
+    format!("tried {}", candidates.join(", "))
+}
+
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    #[error("No device using the {driver} driver: {}", candidates_list(.candidates))]
+    NoMatchingDriver {
+        driver: String,
+        candidates: Vec<Candidate>,
+    },
+
+    #[error("No device #{index} using the {driver} driver: {}", candidates_list(.candidates))]
+    NoMatchingIndex {
+        driver: String,
+        index: usize,
+        candidates: Vec<Candidate>,
+    },
+
+    #[error("No device at bus ID {bus_id}: {}", candidates_list(.candidates))]
+    NoMatchingBusId {
+        bus_id: String,
+        candidates: Vec<Candidate>,
+    },
+
+    #[error("No render node for {}", .path.display())]
+    NoRenderNode { path: PathBuf },
+
+    #[error("Permission denied while opening {}: {}", .path.display(), candidates_list(.candidates))]
+    PermissionDenied {
+        path: PathBuf,
+        candidates: Vec<Candidate>,
+    },
+
+    #[error("Couldn't query the driver of {}: {source}", .path.display())]
+    Ioctl {
+        path: PathBuf,
+        source: io::Error,
+        candidates: Vec<Candidate>,
+    },
+
+    #[error("Couldn't open {}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl DeviceError {
+    fn open(path: &Path, source: io::Error) -> Self {
+        if source.kind() == ErrorKind::PermissionDenied {
+            Self::PermissionDenied {
+                path: path.to_path_buf(),
+                candidates: Vec::new(),
+            }
+        } else {
+            Self::Io {
+                path: path.to_path_buf(),
+                source,
+            }
+        }
+    }
+
+    fn with_candidates(self, candidates: Vec<Candidate>) -> Self {
+        match self {
+            Self::PermissionDenied { path, .. } => Self::PermissionDenied { path, candidates },
+            Self::Ioctl { path, source, .. } => Self::Ioctl {
+                path,
+                source,
+                candidates,
+            },
+            err => err,
+        }
+    }
+}
+
+fn query_driver(path: &Path) -> Result<String, DeviceError> {
+    let file = File::open(path).map_err(|e| DeviceError::open(path, e))?;
+
+    get_version(file.as_fd())
+        .map(|version| version.name)
+        .map_err(|source| DeviceError::Ioctl {
+            path: path.to_path_buf(),
+            source,
+            candidates: Vec::new(),
+        })
+}
+
+fn query_bus_id(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+
+    get_unique(file.as_fd()).ok()
+}
+
+fn normalize_bus_id(bus_id: &str) -> &str {
+    ["pci:", "platform:"]
+        .iter()
+        .find_map(|prefix| bus_id.strip_prefix(prefix))
+        .unwrap_or(bus_id)
+}
+
+// Sorts nodes by their minor, so that card10 comes after card2
+fn node_number(path: &Path) -> u32 {
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .and_then(|name| {
+            name.trim_start_matches(|c: char| !c.is_ascii_digit())
+                .parse()
+                .ok()
+        })
+        .unwrap_or(u32::MAX)
+}
+
+#[derive(Default)]
+struct DriverScan {
+    matches: Vec<PathBuf>,
+    candidates: Vec<Candidate>,
+    first_error: Option<DeviceError>,
+}
+
+impl DriverScan {
+    fn into_error(self, module: &str, index: usize) -> DeviceError {
+        match self.first_error {
+            Some(err) => err.with_candidates(self.candidates),
+            None if index == 0 => DeviceError::NoMatchingDriver {
+                driver: module.to_string(),
+                candidates: self.candidates,
+            },
+            None => DeviceError::NoMatchingIndex {
+                driver: module.to_string(),
+                index,
+                candidates: self.candidates,
+            },
+        }
+    }
+}
+
+/// Looks for DRM devices in `/dev/dri`, using sysfs to find out about their
+/// bus and nodes.
+#[derive(Clone, Debug)]
+pub struct DeviceFinder {
+    dev_root: PathBuf,
+    sysfs_root: PathBuf,
+    driver: fn(&Path) -> Result<String, DeviceError>,
+    bus_id: fn(&Path) -> Option<String>,
+}
+
+impl Default for DeviceFinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceFinder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            dev_root: PathBuf::from(DEFAULT_DEV_ROOT),
+            sysfs_root: PathBuf::from(DEFAULT_SYSFS_ROOT),
+            driver: query_driver,
+            bus_id: query_bus_id,
+        }
+    }
+
+    /// Uses another directory than `/dev`, that is expected to hold a `dri`
+    /// directory.
+    #[must_use]
+    pub fn dev_root(mut self, root: &Path) -> Self {
+        self.dev_root = root.to_path_buf();
+        self
+    }
+
+    /// Uses another directory than `/sys`.
+    #[must_use]
+    pub fn sysfs_root(mut self, root: &Path) -> Self {
+        self.sysfs_root = root.to_path_buf();
+        self
+    }
+
+    fn dri_dir(&self) -> PathBuf {
+        self.dev_root.join("dri")
+    }
+
+    fn nodes(&self, prefix: &str) -> Result<Vec<PathBuf>, DeviceError> {
+        let dir = self.dri_dir();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(DeviceError::open(&dir, e)),
+        };
+
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+            .map(|entry| entry.path())
+            .collect();
+
+        paths.sort_by_key(|path| node_number(path));
+
+        Ok(paths)
+    }
+
+    fn sysfs_device(&self, node: &Path) -> Option<PathBuf> {
+        let name = node.file_name()?;
+
+        Some(self.sysfs_root.join("class/drm").join(name).join("device"))
+    }
+
+    fn bus_id(&self, node: &Path) -> Option<String> {
+        // The device link points to the parent device, named after its bus
+        // ID, for example ../../../0000:03:00.0
+        self.sysfs_device(node)
+            .and_then(|device| fs::read_link(device).ok())
+            .and_then(|target| target.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .or_else(|| (self.bus_id)(node))
+    }
+
+    // Looks for the first `limit` devices using the driver
+    fn scan_driver(&self, module: &str, limit: usize) -> Result<DriverScan, DeviceError> {
+        let mut scan = DriverScan::default();
+
+        for path in self.nodes("card")? {
+            if scan.matches.len() == limit {
+                break;
+            }
+
+            match (self.driver)(&path) {
+                Ok(driver) => {
+                    if driver == module {
+                        scan.matches.push(path.clone());
+                    }
+
+                    scan.candidates.push(Candidate {
+                        bus_id: self.bus_id(&path),
+                        driver: Some(driver),
+                        path,
+                    });
+                }
+                // Another device might still be the one we're after.
+                Err(e) => {
+                    scan.first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        Ok(scan)
+    }
+
+    fn find_by_driver(&self, module: &str, index: usize) -> Result<PathBuf, DeviceError> {
+        let mut scan = self.scan_driver(module, index + 1)?;
+
+        if scan.matches.len() > index {
+            return Ok(scan.matches.swap_remove(index));
+        }
+
+        Err(scan.into_error(module, index))
+    }
+
+    fn find_all_by_driver(&self, module: &str) -> Result<Vec<PathBuf>, DeviceError> {
+        let scan = self.scan_driver(module, usize::MAX)?;
+
+        if scan.matches.is_empty() {
+            return Err(scan.into_error(module, 0));
+        }
+
+        Ok(scan.matches)
+    }
+
+    fn find_by_bus_id(&self, bus_id: &str) -> Result<PathBuf, DeviceError> {
+        let mut candidates = Vec::new();
+
+        for path in self.nodes("card")? {
+            let node_bus_id = self.bus_id(&path);
+
+            if node_bus_id.as_deref().map(normalize_bus_id) == Some(normalize_bus_id(bus_id)) {
+                return Ok(path);
+            }
+
+            candidates.push(Candidate {
+                path,
+                driver: None,
+                bus_id: node_bus_id,
+            });
+        }
+
+        Err(DeviceError::NoMatchingBusId {
+            bus_id: bus_id.to_string(),
+            candidates,
+        })
+    }
+
+    fn find_by_path(&self, name: &str) -> Result<PathBuf, DeviceError> {
+        let link = self.dri_dir().join("by-path").join(name);
+
+        fs::canonicalize(&link).map_err(|e| DeviceError::open(&link, e))
+    }
+
+    fn render_node(&self, card: &Path) -> Result<PathBuf, DeviceError> {
+        let no_render_node = || DeviceError::NoRenderNode {
+            path: card.to_path_buf(),
+        };
+
+        // Every node of a device is listed in its drm directory.
+        let drm_dir = self
+            .sysfs_device(card)
+            .ok_or_else(no_render_node)?
+            .join("drm");
+        let entries = fs::read_dir(drm_dir).map_err(|_| no_render_node())?;
+
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .find(|name| name.starts_with("renderD"))
+            .map(|name| self.dri_dir().join(name))
+            .ok_or_else(no_render_node)
+    }
+
+    /// Returns the path to the device node matching `dev`.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`DeviceError`] if no device matches, or if the devices
+    /// can't be queried.
+    pub fn find(&self, dev: &DeviceSpecifier) -> Result<PathBuf, DeviceError> {
+        match dev {
+            DeviceSpecifier::ModuleName(module) => self.find_by_driver(module, 0),
+            DeviceSpecifier::ModuleIndex(module, index) => self.find_by_driver(module, *index),
+            DeviceSpecifier::BusId(bus_id) => self.find_by_bus_id(bus_id),
+            DeviceSpecifier::ByPath(name) => self.find_by_path(name),
+            DeviceSpecifier::RenderNode(dev) => self.render_node(&self.find(dev)?),
+            DeviceSpecifier::Path(path) => match File::open(path) {
+                Ok(_) => Ok(path.clone()),
+                Err(e) => Err(DeviceError::open(path, e)),
+            },
+        }
+    }
+
+    /// Returns the paths to all the device nodes matching `dev`. Only
+    /// [`DeviceSpecifier::ModuleName`] can match several devices.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`DeviceError`] if no device matches, or if the devices
+    /// can't be queried.
+    pub fn find_all(&self, dev: &DeviceSpecifier) -> Result<Vec<PathBuf>, DeviceError> {
+        match dev {
+            DeviceSpecifier::ModuleName(module) => self.find_all_by_driver(module),
+            DeviceSpecifier::RenderNode(dev) => self
+                .find_all(dev)?
+                .iter()
+                .map(|card| self.render_node(card))
+                .collect(),
+            dev => Ok(vec![self.find(dev)?]),
+        }
+    }
+}
+
+pub(crate) fn find_devices(dev: &DeviceSpecifier, all: bool) -> Result<Vec<PathBuf>, DeviceError> {
+    let finder = DeviceFinder::new();
+
+    if all {
+        finder.find_all(dev)
+    } else {
+        finder.find(dev).map(|path| vec![path])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{self, ErrorKind},
+        os::unix::fs::symlink,
+        path::{Path, PathBuf},
+    };
+
+    use crate::tests::TempPath;
+
+    use super::{Candidate, DeviceError, DeviceFinder, DeviceSpecifier};
+
+    // A fake tree with an i915 device on PCI, and two vkms instances. The
+    // device nodes hold the name of their driver.
+    struct FakeTree {
+        root: TempPath,
+    }
+
+    impl FakeTree {
+        fn new(name: &str) -> Self {
+            let tree = Self {
+                root: TempPath::new(&format!("devices-{name}")),
+            };
+
+            tree.add_device(
+                "card0",
+                "renderD128",
+                "i915",
+                "devices/pci0000:00/0000:00:02.0",
+            );
+            tree.add_device("card1", "renderD129", "vkms", "devices/platform/vkms");
+            tree.add_device("card10", "renderD138", "vkms", "devices/platform/vkms.1");
+
+            fs::create_dir_all(tree.dev().join("dri/by-path")).unwrap();
+            symlink(
+                "../card0",
+                tree.dev().join("dri/by-path/pci-0000:00:02.0-card"),
+            )
+            .unwrap();
+
+            tree
+        }
+
+        fn dev(&self) -> PathBuf {
+            self.root.join("dev")
+        }
+
+        fn sys(&self) -> PathBuf {
+            self.root.join("sys")
+        }
+
+        fn add_device(&self, card: &str, render: &str, driver: &str, device: &str) {
+            let dri = self.dev().join("dri");
+            fs::create_dir_all(&dri).unwrap();
+            fs::write(dri.join(card), driver).unwrap();
+            fs::write(dri.join(render), driver).unwrap();
+
+            let device = self.sys().join(device);
+            fs::create_dir_all(device.join("drm").join(card)).unwrap();
+            fs::create_dir_all(device.join("drm").join(render)).unwrap();
+
+            for node in [card, render] {
+                let class = self.sys().join("class/drm").join(node);
+                fs::create_dir_all(&class).unwrap();
+                symlink(&device, class.join("device")).unwrap();
+            }
+        }
+
+        fn finder(&self) -> DeviceFinder {
+            let mut finder = DeviceFinder::new()
+                .dev_root(&self.dev())
+                .sysfs_root(&self.sys());
+
+            finder.driver = |path| Ok(fs::read_to_string(path).unwrap());
+            finder.bus_id = |_| None;
+            finder
+        }
+
+        fn node(&self, name: &str) -> PathBuf {
+            self.dev().join("dri").join(name)
+        }
+    }
+
+    #[test]
+    fn path() {
+        let finder = DeviceFinder::new();
+
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::Path(PathBuf::from("/dev/null")))
+                .unwrap(),
+            PathBuf::from("/dev/null")
+        );
+
+        assert!(matches!(
+            finder.find(&DeviceSpecifier::Path(PathBuf::from("/dev/dri/does-not-exist"))),
+            Err(DeviceError::Io { source, .. }) if source.kind() == ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn driver() {
+        let tree = FakeTree::new("driver");
+        let finder = tree.finder();
+
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::ModuleName(String::from("vkms")))
+                .unwrap(),
+            tree.node("card1")
+        );
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::ModuleName(String::from("i915")))
+                .unwrap(),
+            tree.node("card0")
+        );
+
+        let err = finder
+            .find(&DeviceSpecifier::ModuleName(String::from("amdgpu")))
+            .unwrap_err();
+        let DeviceError::NoMatchingDriver { candidates, .. } = err else {
+            panic!("Unexpected error {err:?}");
+        };
+
+        assert_eq!(
+            candidates,
+            vec![
+                Candidate {
+                    path: tree.node("card0"),
+                    driver: Some(String::from("i915")),
+                    bus_id: Some(String::from("0000:00:02.0")),
+                },
+                Candidate {
+                    path: tree.node("card1"),
+                    driver: Some(String::from("vkms")),
+                    bus_id: Some(String::from("vkms")),
+                },
+                Candidate {
+                    path: tree.node("card10"),
+                    driver: Some(String::from("vkms")),
+                    bus_id: Some(String::from("vkms.1")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn driver_index() {
+        let tree = FakeTree::new("index");
+        let finder = tree.finder();
+
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::ModuleIndex(String::from("vkms"), 0))
+                .unwrap(),
+            tree.node("card1")
+        );
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::ModuleIndex(String::from("vkms"), 1))
+                .unwrap(),
+            tree.node("card10")
+        );
+        assert!(matches!(
+            finder.find(&DeviceSpecifier::ModuleIndex(String::from("vkms"), 2)),
+            Err(DeviceError::NoMatchingIndex { index: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn all_devices() {
+        let tree = FakeTree::new("all");
+        let finder = tree.finder();
+
+        assert_eq!(
+            finder
+                .find_all(&DeviceSpecifier::ModuleName(String::from("vkms")))
+                .unwrap(),
+            vec![tree.node("card1"), tree.node("card10")]
+        );
+        assert_eq!(
+            finder
+                .find_all(&DeviceSpecifier::RenderNode(Box::new(
+                    DeviceSpecifier::ModuleName(String::from("vkms"))
+                )))
+                .unwrap(),
+            vec![tree.node("renderD129"), tree.node("renderD138")]
+        );
+        assert_eq!(
+            finder
+                .find_all(&DeviceSpecifier::ModuleIndex(String::from("vkms"), 1))
+                .unwrap(),
+            vec![tree.node("card10")]
+        );
+        assert!(matches!(
+            finder.find_all(&DeviceSpecifier::ModuleName(String::from("amdgpu"))),
+            Err(DeviceError::NoMatchingDriver { candidates, .. }) if candidates.len() == 3
+        ));
+    }
+
+    #[test]
+    fn bus_id() {
+        let tree = FakeTree::new("bus-id");
+        let finder = tree.finder();
+
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::BusId(String::from("0000:00:02.0")))
+                .unwrap(),
+            tree.node("card0")
+        );
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::BusId(String::from("pci:0000:00:02.0")))
+                .unwrap(),
+            tree.node("card0")
+        );
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::BusId(String::from("platform:vkms.1")))
+                .unwrap(),
+            tree.node("card10")
+        );
+        assert!(matches!(
+            finder.find(&DeviceSpecifier::BusId(String::from("0000:03:00.0"))),
+            Err(DeviceError::NoMatchingBusId { candidates, .. }) if candidates.len() == 3
+        ));
+    }
+
+    #[test]
+    fn by_path() {
+        let tree = FakeTree::new("by-path");
+        let finder = tree.finder();
+
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::ByPath(String::from(
+                    "pci-0000:00:02.0-card"
+                )))
+                .unwrap(),
+            fs::canonicalize(tree.node("card0")).unwrap()
+        );
+        assert!(matches!(
+            finder.find(&DeviceSpecifier::ByPath(String::from(
+                "pci-0000:03:00.0-card"
+            ))),
+            Err(DeviceError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn render_node() {
+        let tree = FakeTree::new("render");
+        let finder = tree.finder();
+
+        assert_eq!(
+            finder
+                .find(&DeviceSpecifier::RenderNode(Box::new(
+                    DeviceSpecifier::ModuleIndex(String::from("vkms"), 1)
+                )))
+                .unwrap(),
+            tree.node("renderD138")
+        );
+        assert!(matches!(
+            finder.find(&DeviceSpecifier::RenderNode(Box::new(
+                DeviceSpecifier::Path(PathBuf::from("/dev/null"))
+            ))),
+            Err(DeviceError::NoRenderNode { .. })
+        ));
+    }
+
+    #[test]
+    fn no_dri_directory() {
+        let finder = DeviceFinder::new()
+            .dev_root(Path::new("/does-not-exist"))
+            .sysfs_root(Path::new("/does-not-exist"));
+
+        assert!(matches!(
+            finder.find(&DeviceSpecifier::ModuleName(String::from("vkms"))),
+            Err(DeviceError::NoMatchingDriver { candidates, .. }) if candidates.is_empty()
+        ));
+    }
+
+    #[test]
+    fn error_messages() {
+        let candidates = vec![
+            Candidate {
+                path: PathBuf::from("/dev/dri/card0"),
+                driver: Some(String::from("i915")),
+                bus_id: Some(String::from("0000:00:02.0")),
+            },
+            Candidate {
+                path: PathBuf::from("/dev/dri/card1"),
+                driver: Some(String::from("simpledrm")),
+                bus_id: None,
+            },
+        ];
+
+        assert_eq!(
+            DeviceError::NoMatchingDriver {
+                driver: String::from("vkms"),
+                candidates: candidates.clone(),
+            }
+            .to_string(),
+            "No device using the vkms driver: tried /dev/dri/card0 (i915, 0000:00:02.0), /dev/dri/card1 (simpledrm)"
+        );
+
+        assert_eq!(
+            DeviceError::NoMatchingDriver {
+                driver: String::from("vkms"),
+                candidates: Vec::new(),
+            }
+            .to_string(),
+            "No device using the vkms driver: no DRM device found"
+        );
+
+        assert_eq!(
+            DeviceError::PermissionDenied {
+                path: PathBuf::from("/dev/dri/card2"),
+                candidates,
+            }
+            .to_string(),
+            "Permission denied while opening /dev/dri/card2: tried /dev/dri/card0 (i915, 0000:00:02.0), /dev/dri/card1 (simpledrm)"
+        );
+
+        assert_eq!(
+            DeviceError::Ioctl {
+                path: PathBuf::from("/dev/dri/card0"),
+                source: io::Error::from_raw_os_error(25),
+                candidates: Vec::new(),
+            }
+            .to_string(),
+            "Couldn't query the driver of /dev/dri/card0: Inappropriate ioctl for device (os error 25)"
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::{fs, io, path::Path, str::FromStr};
+
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{Test, TestError, TestOutcome};
+
+#[derive(Debug, Error)]
+pub enum ExpectationsError {
+    #[error("I/O Error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Line {line}: {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+/// Outcome a test is known to have on a given driver
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpectedOutcome {
+    /// The test fails, crashes or times out
+    Fail,
+
+    /// The test is skipped
+    Skip,
+
+    /// The test doesn't have a consistent outcome
+    Flake,
+}
+
+impl FromStr for ExpectedOutcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            "flake" => Ok(Self::Flake),
+            _ => Err(format!("Unknown outcome {s}, expected fail, skip or flake")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expectation {
+    pub outcome: ExpectedOutcome,
+
+    /// Free-form comment, usually a link to the bug report
+    pub comment: Option<String>,
+}
+
+/// How a test result compares to its expectation
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    Expected,
+    UnexpectedPass,
+    UnexpectedFailure,
+}
+
+impl Verdict {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Expected => "expected",
+            Verdict::UnexpectedPass => "unexpected-pass",
+            Verdict::UnexpectedFailure => "unexpected-failure",
+        }
+    }
+}
+
+/// List of the known issues of a driver.
+///
+/// Each line holds the full name of a test, or a glob pattern matching
+/// several, its expected outcome and an optional comment:
+///
+/// ```text
+/// # Lines starting with a '#' are ignored
+/// cgt::tests::planes::test_formats fail https://gitlab.freedesktop.org/drm/misc/-/issues/42
+/// cgt::tests::atomic::* skip
+/// ```
+///
+/// If several lines match the same test, the first one wins.
+#[derive(Clone, Debug, Default)]
+pub struct Expectations {
+    entries: Vec<(Pattern, Expectation)>,
+}
+
+impl Expectations {
+    /// Reads an expectations file.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`ExpectationsError`] if the file can't be read or isn't
+    /// valid.
+    pub fn load(path: &Path) -> Result<Self, ExpectationsError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    #[must_use]
+    pub fn get(&self, test: &Test) -> Option<&Expectation> {
+        let name = test.full_name();
+
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&name))
+            .map(|(_, expectation)| expectation)
+    }
+
+    /// Compares a test result with what was expected of it. Tests without
+    /// any expectation are expected to either pass or be skipped.
+    #[must_use]
+    pub fn verdict(&self, test: &Test, res: &Result<(), TestError>) -> Verdict {
+        let expected = self.get(test).map(|expectation| expectation.outcome);
+
+        match (TestOutcome::from(res), expected) {
+            (_, Some(ExpectedOutcome::Flake))
+            | (TestOutcome::Pass | TestOutcome::Warn, None)
+            | (TestOutcome::Skip, _)
+            | (
+                TestOutcome::Fail | TestOutcome::Crash | TestOutcome::Timeout,
+                Some(ExpectedOutcome::Fail),
+            ) => Verdict::Expected,
+            (TestOutcome::Pass | TestOutcome::Warn, Some(_)) => Verdict::UnexpectedPass,
+            (TestOutcome::Fail | TestOutcome::Crash | TestOutcome::Timeout, _) => {
+                Verdict::UnexpectedFailure
+            }
+        }
+    }
+}
+
+impl FromStr for Expectations {
+    type Err = ExpectationsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parse_error = |reason: String| ExpectationsError::Parse {
+                line: idx + 1,
+                reason,
+            };
+
+            let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let pattern = Pattern::new(name).map_err(|e| parse_error(e.msg.to_string()))?;
+
+            let rest = rest.trim_start();
+            let (outcome, comment) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+            if outcome.is_empty() {
+                return Err(parse_error(format!("Missing outcome for {name}")));
+            }
+
+            let outcome = outcome.parse().map_err(parse_error)?;
+            let comment = Some(comment.trim())
+                .filter(|comment| !comment.is_empty())
+                .map(ToString::to_string);
+
+            entries.push((pattern, Expectation { outcome, comment }));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{tests::test, TestError};
+
+    use super::{Expectation, Expectations, ExpectationsError, ExpectedOutcome, Verdict};
+
+    const EXPECTATIONS: &str = "
+# vkms known issues
+cgt::tests::dummy::test_fail fail https://example.com/issues/1
+cgt::tests::dummy::test_skip   skip
+cgt::tests::flaky::* flake   not our fault
+cgt::tests::flaky::test_fail fail
+";
+
+    #[test]
+    fn parse() {
+        let expectations: Expectations = EXPECTATIONS.parse().unwrap();
+
+        assert_eq!(
+            expectations.get(&test("cgt::tests::dummy", "test_fail")),
+            Some(&Expectation {
+                outcome: ExpectedOutcome::Fail,
+                comment: Some(String::from("https://example.com/issues/1")),
+            })
+        );
+        assert_eq!(
+            expectations.get(&test("cgt::tests::dummy", "test_skip")),
+            Some(&Expectation {
+                outcome: ExpectedOutcome::Skip,
+                comment: None,
+            })
+        );
+        assert_eq!(
+            expectations
+                .get(&test("cgt::tests::flaky", "test_fail"))
+                .map(|e| e.outcome),
+            Some(ExpectedOutcome::Flake)
+        );
+        assert_eq!(
+            expectations.get(&test("cgt::tests::dummy", "test_pass")),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            "\ncgt::tests::dummy::test_fail".parse::<Expectations>(),
+            Err(ExpectationsError::Parse { line: 2, .. })
+        ));
+        assert!(matches!(
+            "cgt::tests::dummy::test_fail broken".parse::<Expectations>(),
+            Err(ExpectationsError::Parse { line: 1, .. })
+        ));
+        assert!(matches!(
+            "cgt::tests::[ fail".parse::<Expectations>(),
+            Err(ExpectationsError::Parse { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn verdicts() {
+        let expectations: Expectations = EXPECTATIONS.parse().unwrap();
+        let fail = Err(TestError::Unspecified);
+        let skip = Err(TestError::Skipped(String::from("requires <atomic>")));
+        let timeout = Err(TestError::Timeout(Duration::from_secs(1)));
+
+        let verdict = |module, name, res| expectations.verdict(&test(module, name), res);
+
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_pass", &Ok(())),
+            Verdict::Expected
+        );
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_pass", &skip),
+            Verdict::Expected
+        );
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_pass", &fail),
+            Verdict::UnexpectedFailure
+        );
+
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_fail", &fail),
+            Verdict::Expected
+        );
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_fail", &timeout),
+            Verdict::Expected
+        );
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_fail", &Ok(())),
+            Verdict::UnexpectedPass
+        );
+
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_skip", &Ok(())),
+            Verdict::UnexpectedPass
+        );
+        assert_eq!(
+            verdict("cgt::tests::dummy", "test_skip", &fail),
+            Verdict::UnexpectedFailure
+        );
+
+        assert_eq!(
+            verdict("cgt::tests::flaky", "test_fail", &fail),
+            Verdict::Expected
+        );
+        assert_eq!(
+            verdict("cgt::tests::flaky", "test_fail", &Ok(())),
+            Verdict::Expected
+        );
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    any::{type_name, Any},
+    cell::RefCell,
+    os::fd::BorrowedFd,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, RecvTimeoutError, SendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{panic_message, Test, TestDevice, TestError};
+
+/// Value produced by a fixture, shared between all the tests using it
+pub type FixtureValue = Arc<dyn Any + Send + Sync>;
+
+/// Function releasing what a fixture set up, called even if the tests using
+/// it failed
+pub type FixtureTeardown = fn(&FixtureValue) -> Result<(), TestError>;
+
+/// How long a fixture value lives
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FixtureScope {
+    /// Set up before the first test of the suite using it, and torn down
+    /// once the whole suite is done
+    Suite,
+
+    /// Set up before, and torn down after, every test using it
+    Test,
+}
+
+/// Setup and teardown functions registered with `#[cgt_fixture]`. Tests of
+/// the same module use a fixture by taking an argument with the fixture name.
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    pub module_name: &'static str,
+    pub name: &'static str,
+    pub scope: FixtureScope,
+
+    /// Sets the value up with a file on the device: the one of the test for
+    /// test fixtures, and one of their own for suite fixtures, which need to
+    /// clone it to keep it around.
+    pub setup: fn(BorrowedFd<'_>) -> Result<FixtureValue, TestError>,
+    pub teardown: Option<FixtureTeardown>,
+}
+
+inventory::collect!(Fixture);
+
+fn find_fixture(module_name: &str, name: &str) -> Option<&'static Fixture> {
+    inventory::iter::<Fixture>
+        .into_iter()
+        .find(|fixture| fixture.module_name == module_name && fixture.name == name)
+}
+
+// A failed setup is reported for every test using the fixture, so we need to
+// be able to duplicate the error.
+fn setup_error(err: &TestError) -> TestError {
+    match err {
+        TestError::Skipped(reason) => TestError::Skipped(reason.clone()),
+        TestError::FixtureFailed(msg) => TestError::FixtureFailed(msg.clone()),
+        e => TestError::FixtureFailed(e.to_string()),
+    }
+}
+
+type FixtureResult = (&'static Fixture, Result<FixtureValue, TestError>);
+
+/// Fixture values set up for a suite or a test, in setup order
+#[derive(Default)]
+pub(crate) struct FixtureSet {
+    values: Vec<FixtureResult>,
+}
+
+impl FixtureSet {
+    fn get(&self, name: &str) -> Option<&Result<FixtureValue, TestError>> {
+        self.values
+            .iter()
+            .find(|(fixture, _)| fixture.name == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Tears down all the fixtures that were successfully set up, in the
+    /// reverse order, even if some of them fail. Returns the first error.
+    pub(crate) fn teardown(&mut self) -> Result<(), TestError> {
+        let mut res = Ok(());
+
+        while let Some((fixture, value)) = self.values.pop() {
+            let (Some(teardown), Ok(value)) = (fixture.teardown, value) else {
+                continue;
+            };
+
+            let fixture_res = panic::catch_unwind(AssertUnwindSafe(|| teardown(&value)))
+                .unwrap_or_else(|payload| Err(TestError::Panicked(panic_message(payload.as_ref()))))
+                .map_err(|e| TestError::FixtureFailed(format!("{} teardown: {e}", fixture.name)));
+
+            res = res.and(fixture_res);
+        }
+
+        res
+    }
+}
+
+fn setup_fixture(fixture: &Fixture, device: &TestDevice) -> Result<FixtureValue, TestError> {
+    // Suite fixtures outlive the test they're set up for, and whatever they
+    // do to their file mustn't leak into it.
+    let suite_device;
+    let device = match fixture.scope {
+        FixtureScope::Suite => {
+            suite_device = TestDevice::open(&device.path);
+            &suite_device
+        }
+        FixtureScope::Test => device,
+    };
+
+    device
+        .fd()
+        .and_then(|fd| {
+            panic::catch_unwind(AssertUnwindSafe(|| (fixture.setup)(fd)))
+                .unwrap_or_else(|payload| Err(TestError::Panicked(panic_message(payload.as_ref()))))
+        })
+        .map_err(|e| match e {
+            TestError::Skipped(reason) => TestError::Skipped(reason),
+            e => TestError::FixtureFailed(format!("{}: {e}", fixture.name)),
+        })
+}
+
+// Stops at the first failure, since the test can't run anyway. Every result
+// is handed over as soon as it's known.
+fn setup_all(
+    fixtures: &[&'static Fixture],
+    device: &TestDevice,
+    mut done: impl FnMut(FixtureResult),
+) {
+    for fixture in fixtures {
+        let value = setup_fixture(fixture, device);
+        let failed = value.is_err();
+
+        done((*fixture, value));
+
+        if failed {
+            break;
+        }
+    }
+}
+
+// A stuck setup can't be stopped, so it's left running on its own thread. The
+// fixtures set up before it still get torn down along with the others, and
+// the ones only set up once we stopped waiting are torn down right away.
+fn setup_all_with_timeout(
+    fixtures: Vec<&'static Fixture>,
+    device: &TestDevice,
+    timeout: Duration,
+) -> (Vec<FixtureResult>, Result<(), TestError>) {
+    let (sender, receiver) = mpsc::channel();
+    let device = device.try_clone();
+    let deadline = Instant::now() + timeout;
+
+    thread::spawn(move || {
+        setup_all(&fixtures, &device, |value| {
+            if let Err(SendError(value)) = sender.send(value) {
+                let _ = FixtureSet {
+                    values: vec![value],
+                }
+                .teardown();
+            }
+        });
+    });
+
+    let mut values = Vec::new();
+
+    loop {
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(value) => values.push(value),
+            Err(RecvTimeoutError::Disconnected) => return (values, Ok(())),
+            Err(RecvTimeoutError::Timeout) => return (values, Err(TestError::Timeout(timeout))),
+        }
+    }
+}
+
+/// Sets up the fixtures a test needs, reusing the suite fixtures already set
+/// up by previous tests. The setups taking longer than `timeout` altogether
+/// make the test time out.
+pub(crate) fn setup_fixtures(
+    test: &Test,
+    device: &TestDevice,
+    suite: &mut FixtureSet,
+    local: &mut FixtureSet,
+    timeout: Option<Duration>,
+) -> Result<Fixtures, TestError> {
+    let mut needed = Vec::new();
+    let mut missing = Vec::new();
+
+    for name in test.fixtures.into_iter().flatten() {
+        let Some(fixture) = find_fixture(test.module_name, name) else {
+            return Err(TestError::FixtureFailed(format!(
+                "no fixture named {name} in {}",
+                test.module_name
+            )));
+        };
+
+        let set = match fixture.scope {
+            FixtureScope::Suite => &*suite,
+            FixtureScope::Test => &*local,
+        };
+
+        if set.get(name).is_none() {
+            missing.push(fixture);
+        }
+
+        needed.push(fixture);
+    }
+
+    let (values, res) = match timeout {
+        Some(timeout) if !missing.is_empty() => setup_all_with_timeout(missing, device, timeout),
+        _ => {
+            let mut values = Vec::new();
+            setup_all(&missing, device, |value| values.push(value));
+            (values, Ok(()))
+        }
+    };
+
+    // Even if a setup got stuck, the ones done before it need tearing down.
+    for (fixture, value) in values {
+        match fixture.scope {
+            FixtureScope::Suite => suite.values.push((fixture, value)),
+            FixtureScope::Test => local.values.push((fixture, value)),
+        }
+    }
+
+    res?;
+
+    let mut fixtures = Fixtures::default();
+
+    for fixture in needed {
+        let set = match fixture.scope {
+            FixtureScope::Suite => &*suite,
+            FixtureScope::Test => &*local,
+        };
+
+        match set.get(fixture.name) {
+            Some(Ok(value)) => fixtures.0.push((fixture.name, Arc::clone(value))),
+            Some(Err(e)) => return Err(setup_error(e)),
+            // Fixtures are only left out after one that failed, which we
+            // returned for already.
+            None => unreachable!("{} isn't set up", fixture.name),
+        }
+    }
+
+    Ok(fixtures)
+}
+
+/// Fixture values made available to a running test
+#[derive(Clone, Default)]
+pub(crate) struct Fixtures(Vec<(&'static str, FixtureValue)>);
+
+thread_local! {
+    static CURRENT_FIXTURES: RefCell<Fixtures> = RefCell::new(Fixtures::default());
+}
+
+impl Fixtures {
+    /// Makes the fixtures available to [`fixture`] on the current thread
+    /// until `f` returns.
+    pub(crate) fn with<R>(&self, f: impl FnOnce() -> R) -> R {
+        let prev = CURRENT_FIXTURES.with(|current| current.replace(self.clone()));
+        let res = f();
+
+        CURRENT_FIXTURES.with(|current| current.replace(prev));
+
+        res
+    }
+}
+
+/// Returns the value of the fixture `name` for the running test. Used by the
+/// code generated by the `cgt_test*` macros.
+///
+/// # Errors
+///
+/// Will return [`TestError::FixtureFailed`] if the test doesn't use the
+/// fixture, or if the fixture value isn't a `T`.
+pub fn fixture<T: Any + Send + Sync>(name: &str) -> Result<Arc<T>, TestError> {
+    let value = CURRENT_FIXTURES.with(|current| {
+        current
+            .borrow()
+            .0
+            .iter()
+            .find(|(fixture, _)| *fixture == name)
+            .map(|(_, value)| Arc::clone(value))
+    });
+
+    value
+        .ok_or_else(|| TestError::FixtureFailed(format!("{name} isn't set up")))?
+        .downcast()
+        .map_err(|_| TestError::FixtureFailed(format!("{name} isn't a {}", type_name::<T>())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::fd::BorrowedFd,
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{tests::test, TestDevice, TestError};
+
+    use super::{fixture, setup_fixtures, Fixture, FixtureScope, FixtureSet, FixtureValue};
+
+    static SETUPS: AtomicUsize = AtomicUsize::new(0);
+    static TEARDOWNS: AtomicUsize = AtomicUsize::new(0);
+    static LATE_TEARDOWNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counter(_fd: BorrowedFd<'_>) -> Result<FixtureValue, TestError> {
+        Ok(Arc::new(SETUPS.fetch_add(1, Ordering::SeqCst)))
+    }
+
+    fn count_teardown(_value: &FixtureValue) -> Result<(), TestError> {
+        TEARDOWNS.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn answer(_fd: BorrowedFd<'_>) -> Result<FixtureValue, TestError> {
+        Ok(Arc::new(42_usize))
+    }
+
+    fn missing(_fd: BorrowedFd<'_>) -> Result<FixtureValue, TestError> {
+        Err(TestError::Skipped(String::from("no connector connected")))
+    }
+
+    fn broken(_fd: BorrowedFd<'_>) -> Result<FixtureValue, TestError> {
+        panic!("oops")
+    }
+
+    fn stuck(_fd: BorrowedFd<'_>) -> Result<FixtureValue, TestError> {
+        thread::sleep(Duration::from_secs(10));
+        Ok(Arc::new(()))
+    }
+
+    fn slow(_fd: BorrowedFd<'_>) -> Result<FixtureValue, TestError> {
+        thread::sleep(Duration::from_millis(200));
+        Ok(Arc::new(()))
+    }
+
+    fn late_teardown(_value: &FixtureValue) -> Result<(), TestError> {
+        LATE_TEARDOWNS.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn failing_teardown(_value: &FixtureValue) -> Result<(), TestError> {
+        Err(TestError::Unspecified)
+    }
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "suite_counter",
+        scope: FixtureScope::Suite,
+        setup: counter,
+        teardown: Some(count_teardown),
+    });
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "test_counter",
+        scope: FixtureScope::Test,
+        setup: counter,
+        teardown: Some(count_teardown),
+    });
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "answer",
+        scope: FixtureScope::Test,
+        setup: answer,
+        teardown: None,
+    });
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "missing",
+        scope: FixtureScope::Suite,
+        setup: missing,
+        teardown: Some(count_teardown),
+    });
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "stuck",
+        scope: FixtureScope::Suite,
+        setup: stuck,
+        teardown: None,
+    });
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "slow",
+        scope: FixtureScope::Test,
+        setup: slow,
+        teardown: Some(late_teardown),
+    });
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "leaky",
+        scope: FixtureScope::Test,
+        setup: answer,
+        teardown: Some(failing_teardown),
+    });
+
+    inventory::submit!(Fixture {
+        module_name: "cgt_core::fixture::tests",
+        name: "broken",
+        scope: FixtureScope::Test,
+        setup: broken,
+        teardown: None,
+    });
+
+    fn fixture_test(fixtures: &[&'static str]) -> crate::Test {
+        let mut test = test("cgt_core::fixture::tests", "test_fixture");
+
+        for (slot, name) in test.fixtures.iter_mut().zip(fixtures) {
+            *slot = Some(name);
+        }
+
+        test
+    }
+
+    // The counters are shared, so everything has to happen in a single test.
+    #[test]
+    fn scopes() {
+        let device = TestDevice::open(Path::new("/dev/null"));
+        let test = fixture_test(&["suite_counter", "test_counter"]);
+        let mut suite = FixtureSet::default();
+        let mut values = Vec::new();
+
+        // Setups with a timeout run on another thread, which doesn't change
+        // anything.
+        for timeout in [None, Some(Duration::from_secs(10))] {
+            let mut local = FixtureSet::default();
+            let fixtures = setup_fixtures(&test, &device, &mut suite, &mut local, timeout).unwrap();
+
+            values.push(fixtures.with(|| {
+                (
+                    *fixture::<usize>("suite_counter").unwrap(),
+                    *fixture::<usize>("test_counter").unwrap(),
+                )
+            }));
+
+            local.teardown().unwrap();
+        }
+
+        // The suite fixture is only set up once, the test one every time.
+        assert_eq!(values, vec![(0, 1), (0, 2)]);
+        assert_eq!(SETUPS.load(Ordering::SeqCst), 3);
+        assert_eq!(TEARDOWNS.load(Ordering::SeqCst), 2);
+
+        suite.teardown().unwrap();
+        assert_eq!(TEARDOWNS.load(Ordering::SeqCst), 3);
+
+        // Failed setups are reported to every test, and never torn down.
+        let test = fixture_test(&["missing"]);
+        let mut local = FixtureSet::default();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                setup_fixtures(&test, &device, &mut suite, &mut local, None),
+                Err(TestError::Skipped(_))
+            ));
+        }
+
+        suite.teardown().unwrap();
+        assert_eq!(TEARDOWNS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn setup_errors() {
+        let device = TestDevice::open(Path::new("/dev/null"));
+        let mut suite = FixtureSet::default();
+        let mut local = FixtureSet::default();
+
+        assert_eq!(
+            setup_fixtures(
+                &fixture_test(&["broken"]),
+                &device,
+                &mut suite,
+                &mut local,
+                None
+            )
+            .err(),
+            Some(TestError::FixtureFailed(String::from(
+                "broken: Test panicked: oops"
+            )))
+        );
+        assert!(matches!(
+            setup_fixtures(
+                &fixture_test(&["unknown"]),
+                &device,
+                &mut suite,
+                &mut local,
+                None
+            ),
+            Err(TestError::FixtureFailed(_))
+        ));
+    }
+
+    #[test]
+    fn setup_timeout() {
+        let device = TestDevice::open(Path::new("/dev/null"));
+        let mut suite = FixtureSet::default();
+        let mut local = FixtureSet::default();
+        let timeout = Duration::from_millis(100);
+
+        assert_eq!(
+            setup_fixtures(
+                &fixture_test(&["answer", "stuck"]),
+                &device,
+                &mut suite,
+                &mut local,
+                Some(timeout)
+            )
+            .err(),
+            Some(TestError::Timeout(timeout))
+        );
+
+        // What got set up before the stuck setup is ours to tear down.
+        assert!(suite.values.is_empty());
+        assert_eq!(local.values.len(), 1);
+        assert_eq!(local.values[0].0.name, "answer");
+
+        // A setup done after the timeout is torn down as soon as it's done.
+        let mut local = FixtureSet::default();
+
+        assert_eq!(
+            setup_fixtures(
+                &fixture_test(&["slow"]),
+                &device,
+                &mut suite,
+                &mut local,
+                Some(timeout)
+            )
+            .err(),
+            Some(TestError::Timeout(timeout))
+        );
+        assert!(local.values.is_empty());
+
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(LATE_TEARDOWNS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn teardown_errors() {
+        let device = TestDevice::open(Path::new("/dev/null"));
+        let mut suite = FixtureSet::default();
+        let mut local = FixtureSet::default();
+
+        setup_fixtures(
+            &fixture_test(&["leaky", "answer"]),
+            &device,
+            &mut suite,
+            &mut local,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            local.teardown(),
+            Err(TestError::FixtureFailed(String::from(
+                "leaky teardown: Unknown Error"
+            )))
+        );
+        assert!(local.values.is_empty());
+    }
+
+    #[test]
+    fn wrong_type() {
+        let device = TestDevice::open(Path::new("/dev/null"));
+        let mut suite = FixtureSet::default();
+        let mut local = FixtureSet::default();
+        let fixtures = setup_fixtures(
+            &fixture_test(&["answer"]),
+            &device,
+            &mut suite,
+            &mut local,
+            None,
+        )
+        .unwrap();
+
+        fixtures.with(|| {
+            assert_eq!(*fixture::<usize>("answer").unwrap(), 42);
+            assert!(fixture::<u32>("answer").is_err());
+            assert!(fixture::<usize>("suite_counter").is_err());
+        });
+        assert!(fixture::<usize>("answer").is_err());
+    }
+}
@@ -0,0 +1,395 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use drm_helpers::DriverVersion;
+use nix::sys::utsname::uname;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    Expectation, KernelMessage, Taint, Test, TestError, TestOutcome, TestResultWriter, Verdict,
+};
+
+// The results format version igt_runner currently emits
+const RESULTS_VERSION: u32 = 10;
+
+const RESULT_NAMES: [&str; 11] = [
+    "abort",
+    "crash",
+    "dmesg-fail",
+    "dmesg-warn",
+    "fail",
+    "incomplete",
+    "notrun",
+    "pass",
+    "skip",
+    "timeout",
+    "warn",
+];
+
+fn time_attribute(start: Duration, end: Duration) -> Value {
+    json!({
+        "__type__": "TimeAttribute",
+        "start": start.as_secs_f64(),
+        "end": end.as_secs_f64(),
+    })
+}
+
+/// Name of a test the way igt_runner would name it, that is
+/// `cgt@<module>@<test>`. IGT names can't hold `::`, so the components of the
+/// module path following the crate name are joined with `_`.
+#[must_use]
+pub fn igt_test_name(test: &Test) -> String {
+    format!(
+        "cgt@{}@{}",
+        igt_binary_name(test.module_name),
+        test.test_name
+    )
+}
+
+// Modules with the same name in different parents must not end up with the
+// same binary name, or their results would overwrite each other.
+fn igt_binary_name(module_name: &str) -> String {
+    let mut components = module_name.split("::");
+
+    if module_name.contains("::") {
+        components.next();
+    }
+
+    components.collect::<Vec<_>>().join("_")
+}
+
+#[derive(Debug, Default)]
+struct Totals(BTreeMap<&'static str, u64>);
+
+impl Totals {
+    fn add(&mut self, result: &'static str) {
+        *self.0.entry(result).or_default() += 1;
+    }
+
+    fn to_json(&self) -> Value {
+        RESULT_NAMES
+            .iter()
+            .map(|name| (name.to_string(), json!(self.0.get(name).unwrap_or(&0))))
+            .collect::<Map<String, Value>>()
+            .into()
+    }
+}
+
+/// Writes the results in the `results.json` layout of igt_runner, so that
+/// they can be fed to the tools consuming IGT results, such as `piglit
+/// summary`.
+///
+/// The file is only written once the run is over. If the tests ran against
+/// several devices, the name of the device node is appended to the test names
+/// as a dynamic subtest, e.g. `cgt@tests_dummy@test_pass@card1`.
+pub struct IgtResultWriter {
+    output: Box<dyn Write>,
+    run_start: Instant,
+    suite_start: Duration,
+    test_start: Duration,
+    device: String,
+    num_devices: usize,
+    tests: Vec<(String, String, Value)>,
+    runtimes: BTreeMap<String, (Duration, Duration)>,
+    totals: BTreeMap<String, Totals>,
+    suite_name: String,
+    dmesg: String,
+    err: String,
+    verdict: String,
+}
+
+impl IgtResultWriter {
+    #[must_use]
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self {
+            output,
+            run_start: Instant::now(),
+            suite_start: Duration::ZERO,
+            test_start: Duration::ZERO,
+            device: String::new(),
+            num_devices: 0,
+            tests: Vec::new(),
+            runtimes: BTreeMap::new(),
+            totals: BTreeMap::new(),
+            suite_name: String::new(),
+            dmesg: String::new(),
+            err: String::new(),
+            verdict: String::new(),
+        }
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        let uname = uname().map_or_else(
+            |_| String::new(),
+            |u| {
+                format!(
+                    "{} {} {} {} {}",
+                    u.sysname().to_string_lossy(),
+                    u.nodename().to_string_lossy(),
+                    u.release().to_string_lossy(),
+                    u.version().to_string_lossy(),
+                    u.machine().to_string_lossy()
+                )
+            },
+        );
+
+        let totals: Map<String, Value> = self
+            .totals
+            .iter()
+            .map(|(group, totals)| (group.clone(), totals.to_json()))
+            .collect();
+
+        let tests: Map<String, Value> = self
+            .tests
+            .iter()
+            .map(|(name, device, result)| {
+                let name = if self.num_devices > 1 {
+                    format!("{name}@{device}")
+                } else {
+                    name.clone()
+                };
+
+                (name, result.clone())
+            })
+            .collect();
+
+        let runtimes: Map<String, Value> = self
+            .runtimes
+            .iter()
+            .map(|(suite, (start, end))| {
+                (
+                    suite.clone(),
+                    json!({ "time": time_attribute(*start, *end) }),
+                )
+            })
+            .collect();
+
+        let report = json!({
+            "__type__": "TestrunResult",
+            "results_version": RESULTS_VERSION,
+            "name": "cgt",
+            "uname": uname,
+            "time_elapsed": time_attribute(Duration::ZERO, self.run_start.elapsed()),
+            "tests": tests,
+            "totals": totals,
+            "runtimes": runtimes,
+        });
+
+        serde_json::to_writer_pretty(&mut self.output, &report)?;
+        writeln!(self.output)?;
+        self.output.flush()
+    }
+}
+
+impl TestResultWriter for IgtResultWriter {
+    fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    fn start_run(&mut self) {
+        self.run_start = Instant::now();
+    }
+
+    fn start_device(&mut self, device: &Path, _version: Option<&DriverVersion>) {
+        self.num_devices += 1;
+        self.device = device.file_name().map_or_else(
+            || device.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+    }
+
+    fn start_suite(&mut self, name: &str, _tests: &[Test]) {
+        self.suite_name = format!("cgt@{}", igt_binary_name(name));
+        self.suite_start = self.run_start.elapsed();
+    }
+
+    fn write_test(&mut self, _test: &Test) {
+        self.test_start = self.run_start.elapsed();
+    }
+
+    fn write_result(&mut self, test: &Test, res: &Result<(), TestError>, duration: Duration) {
+        let end = self.test_start + duration;
+        let result = match res {
+            Err(TestError::DmesgFail(_)) => "dmesg-fail",
+            Err(TestError::DmesgWarn(_)) => "dmesg-warn",
+            res => TestOutcome::from(res).as_str(),
+        };
+
+        let out = match res {
+            Ok(()) => String::new(),
+            Err(TestError::Skipped(reason)) => format!("{reason}\n"),
+            Err(e) => format!("{e}\n"),
+        } + &std::mem::take(&mut self.verdict);
+
+        self.tests.push((
+            igt_test_name(test),
+            self.device.clone(),
+            json!({
+                "out": out,
+                "err": std::mem::take(&mut self.err),
+                "dmesg": std::mem::take(&mut self.dmesg),
+                "result": result,
+                "time": time_attribute(self.test_start, end),
+            }),
+        ));
+
+        // igt_runner keeps totals for the whole run, under both "" and
+        // "root", and for every level of the test name.
+        for group in ["", "root", "cgt", &self.suite_name] {
+            self.totals
+                .entry(group.to_string())
+                .or_default()
+                .add(result);
+        }
+    }
+
+    fn write_kernel_log(&mut self, _test: &Test, messages: &[KernelMessage]) {
+        self.dmesg = messages.iter().map(|msg| format!("{msg}\n")).collect();
+    }
+
+    fn write_taint(&mut self, _test: &Test, taint: Taint) {
+        self.err = format!("Kernel tainted: {taint}\n");
+    }
+
+    // The IGT format has no room for it, so the verdict ends up in the output
+    // of the test, for the tests with an expectation or failing unexpectedly.
+    fn write_verdict(&mut self, _test: &Test, verdict: Verdict, expected: Option<&Expectation>) {
+        if expected.is_none() && verdict == Verdict::Expected {
+            return;
+        }
+
+        self.verdict = match expected.and_then(|e| e.comment.as_deref()) {
+            Some(comment) => format!("Verdict: {} ({comment})\n", verdict.as_str()),
+            None => format!("Verdict: {}\n", verdict.as_str()),
+        };
+    }
+
+    fn end_suite(&mut self, duration: Duration) {
+        let end = self.suite_start + duration;
+
+        // Suites run once per device, the runtime spans all of them.
+        self.runtimes
+            .entry(self.suite_name.clone())
+            .or_insert((self.suite_start, end))
+            .1 = end;
+    }
+
+    fn end_run(&mut self) {
+        if let Err(e) = self.write_report() {
+            eprintln!("Couldn't write the IGT results: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::tests::{test, write_run, write_run_on, write_verdicts, Buffer};
+
+    use super::{igt_test_name, IgtResultWriter};
+
+    #[test]
+    fn test_name() {
+        assert_eq!(
+            igt_test_name(&test("cgt::tests::planes", "test_formats")),
+            "cgt@tests_planes@test_formats"
+        );
+    }
+
+    #[test]
+    fn same_module_name() {
+        assert_ne!(
+            igt_test_name(&test("cgt::kms::planes", "test_formats")),
+            igt_test_name(&test("cgt::legacy::planes", "test_formats"))
+        );
+        assert_eq!(
+            igt_test_name(&test("cgt::kms::planes", "test_formats")),
+            "cgt@kms_planes@test_formats"
+        );
+    }
+
+    #[test]
+    fn report() {
+        let buffer = Buffer::default();
+        let mut writer = IgtResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run(&mut writer);
+
+        let report: Value = serde_json::from_str(&buffer.contents()).unwrap();
+
+        assert_eq!(report["__type__"], "TestrunResult");
+        assert_eq!(report["results_version"], 10);
+
+        let tests = &report["tests"];
+        assert_eq!(tests["cgt@tests_dummy@test_pass"]["result"], "pass");
+        assert_eq!(tests["cgt@tests_dummy@test_fail"]["result"], "fail");
+        assert_eq!(
+            tests["cgt@tests_dummy@test_fail"]["out"],
+            "Values 1 and 2 are not equal\n"
+        );
+        assert_eq!(tests["cgt@tests_dummy@test_skip"]["result"], "skip");
+        assert_eq!(tests["cgt@tests_dummy@test_timeout"]["result"], "timeout");
+        assert_eq!(tests["cgt@tests_dummy@test_pass"]["dmesg"], "");
+        assert_eq!(
+            tests["cgt@tests_dummy@test_pass"]["time"]["__type__"],
+            "TimeAttribute"
+        );
+
+        for group in ["", "root", "cgt", "cgt@tests_dummy"] {
+            let totals = &report["totals"][group];
+
+            assert_eq!(totals["pass"], 1);
+            assert_eq!(totals["fail"], 1);
+            assert_eq!(totals["skip"], 1);
+            assert_eq!(totals["timeout"], 1);
+            assert_eq!(totals["crash"], 0);
+        }
+
+        assert!(report["runtimes"]["cgt@tests_dummy"]["time"].is_object());
+    }
+
+    #[test]
+    fn verdicts() {
+        let buffer = Buffer::default();
+        let mut writer = IgtResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_verdicts(&mut writer);
+
+        let report: Value = serde_json::from_str(&buffer.contents()).unwrap();
+
+        let tests = &report["tests"];
+        assert_eq!(
+            tests["cgt@tests_dummy@test_known"]["out"],
+            "Values 1 and 2 are not equal\nVerdict: expected (https://bugs.example/42)\n"
+        );
+        assert_eq!(
+            tests["cgt@tests_dummy@test_flaky"]["out"],
+            "Verdict: expected\n"
+        );
+        assert_eq!(tests["cgt@tests_dummy@test_pass"]["out"], "");
+    }
+
+    #[test]
+    fn several_devices() {
+        let buffer = Buffer::default();
+        let mut writer = IgtResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run_on(&mut writer, &["/dev/dri/card0", "/dev/dri/card1"]);
+
+        let report: Value = serde_json::from_str(&buffer.contents()).unwrap();
+
+        let tests = &report["tests"];
+        assert_eq!(tests["cgt@tests_dummy@test_pass@card0"]["result"], "pass");
+        assert_eq!(tests["cgt@tests_dummy@test_pass@card1"]["result"], "pass");
+        assert_eq!(tests["cgt@tests_dummy@test_fail@card1"]["result"], "fail");
+        assert!(tests["cgt@tests_dummy@test_pass"].is_null());
+
+        assert_eq!(report["totals"]["cgt@tests_dummy"]["pass"], 2);
+        assert!(report["runtimes"]["cgt@tests_dummy"]["time"].is_object());
+    }
+}
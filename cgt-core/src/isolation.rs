@@ -0,0 +1,277 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::fd::FromRawFd,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+    sys::{
+        signal::{kill, Signal},
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{fork, pipe, ForkResult, Pid},
+};
+
+use crate::{run_test_catch_unwind, Fixtures, Test, TestDevice, TestError};
+
+const SEPARATOR: char = '\0';
+
+// TestError can't cross a process boundary as is, so the child sends it back
+// to its parent as a list of NUL-separated fields, the first one being the
+// variant name.
+fn encode_error(err: &TestError) -> String {
+    let fields: Vec<String> = match err {
+        TestError::ConditionUnmet(cond) => vec!["ConditionUnmet".into(), cond.clone()],
+        TestError::Crashed(reason) => vec!["Crashed".into(), reason.clone()],
+        TestError::DmesgFail(msg) => vec!["DmesgFail".into(), msg.clone()],
+        TestError::DmesgWarn(msg) => vec!["DmesgWarn".into(), msg.clone()],
+        TestError::FixtureFailed(msg) => vec!["FixtureFailed".into(), msg.clone()],
+        TestError::Io(e) => vec![
+            "Io".into(),
+            e.raw_os_error().map_or_else(String::new, |e| e.to_string()),
+            e.to_string(),
+        ],
+        TestError::NotEqual(l, r) => vec!["NotEqual".into(), l.clone(), r.clone()],
+        TestError::Panicked(msg) => vec!["Panicked".into(), msg.clone()],
+        TestError::ResultNotError(res) => vec!["ResultNotError".into(), res.clone()],
+        TestError::ResultNotOk(res) => vec!["ResultNotOk".into(), res.clone()],
+        TestError::Skipped(reason) => vec!["Skipped".into(), reason.clone()],
+        TestError::Timeout(timeout) => vec!["Timeout".into(), timeout.as_nanos().to_string()],
+        TestError::Unspecified => vec!["Unspecified".into()],
+    };
+
+    fields.join(&SEPARATOR.to_string())
+}
+
+fn decode_error(data: &str) -> TestError {
+    let fields: Vec<&str> = data.split(SEPARATOR).collect();
+    let field = |idx: usize| fields.get(idx).copied().unwrap_or_default().to_string();
+
+    match fields[0] {
+        "ConditionUnmet" => TestError::ConditionUnmet(field(1)),
+        "Crashed" => TestError::Crashed(field(1)),
+        "DmesgFail" => TestError::DmesgFail(field(1)),
+        "DmesgWarn" => TestError::DmesgWarn(field(1)),
+        "FixtureFailed" => TestError::FixtureFailed(field(1)),
+        "Io" => match field(1).parse() {
+            Ok(errno) => std::io::Error::from_raw_os_error(errno).into(),
+            Err(_) => std::io::Error::other(field(2)).into(),
+        },
+        "NotEqual" => TestError::NotEqual(field(1), field(2)),
+        "Panicked" => TestError::Panicked(field(1)),
+        "ResultNotError" => TestError::ResultNotError(field(1)),
+        "ResultNotOk" => TestError::ResultNotOk(field(1)),
+        "Skipped" => TestError::Skipped(field(1)),
+        "Timeout" => TestError::Timeout(Duration::from_nanos(field(1).parse().unwrap_or_default())),
+        _ => TestError::Unspecified,
+    }
+}
+
+fn run_child(test: &Test, device: &TestDevice, fixtures: &Fixtures, mut output: File) -> ! {
+    let code = match run_test_catch_unwind(test, device, fixtures) {
+        Ok(()) => 0,
+        Err(ref e) => {
+            if output.write_all(encode_error(e).as_bytes()).is_err() {
+                2
+            } else {
+                1
+            }
+        }
+    };
+
+    std::process::exit(code)
+}
+
+// Reads everything the child sends us until it closes its end of the pipe.
+// Returns None if the deadline expires first.
+fn read_child_output(reader: &mut File, deadline: Option<Instant>) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.checked_duration_since(Instant::now())?;
+
+                i32::try_from(remaining.as_millis()).unwrap_or(i32::MAX)
+            }
+            None => -1,
+        };
+
+        match poll(&mut [PollFd::new(reader, PollFlags::POLLIN)], timeout) {
+            Ok(0) => return None,
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(_) => return Some(data),
+        }
+
+        match reader.read(&mut buf) {
+            Ok(0) => return Some(data),
+            Ok(len) => data.extend_from_slice(&buf[..len]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(_) => return Some(data),
+        }
+    }
+}
+
+fn wait_child(child: Pid, data: &[u8]) -> Result<(), TestError> {
+    match waitpid(child, None)? {
+        WaitStatus::Exited(_, 0) => Ok(()),
+        WaitStatus::Exited(_, 1) if !data.is_empty() => {
+            Err(decode_error(&String::from_utf8_lossy(data)))
+        }
+        WaitStatus::Exited(_, code) => Err(TestError::Crashed(format!(
+            "test process exited with status {code}"
+        ))),
+        WaitStatus::Signaled(_, sig, core) => Err(TestError::Crashed(format!(
+            "test process killed by {sig}{}",
+            if core { " (core dumped)" } else { "" }
+        ))),
+        status => Err(TestError::Crashed(format!(
+            "unexpected test process status {status:?}"
+        ))),
+    }
+}
+
+pub(crate) fn run_test_isolated(
+    test: &Test,
+    device: &TestDevice,
+    fixtures: &Fixtures,
+    timeout: Option<Duration>,
+) -> Result<(), TestError> {
+    // Anything still buffered would otherwise be output by both processes.
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+
+    let (read_fd, write_fd) = pipe()?;
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd)) };
+
+    match unsafe { fork() }? {
+        ForkResult::Child => {
+            drop(reader);
+            run_child(test, device, fixtures, writer)
+        }
+        ForkResult::Parent { child } => {
+            drop(writer);
+
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+            if let Some(data) = read_child_output(&mut reader, deadline) {
+                wait_child(child, &data)
+            } else {
+                let _ = kill(child, Signal::SIGKILL);
+                let _ = waitpid(child, None);
+
+                Err(TestError::Timeout(timeout.unwrap_or_default()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, thread, time::Duration};
+
+    use crate::{Fixtures, Test, TestDevice, TestError, TestFunction};
+
+    use super::{decode_error, encode_error, run_test_isolated};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn roundtrip(err: &TestError) {
+        assert_eq!(&decode_error(&encode_error(err)), err);
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        roundtrip(&TestError::ConditionUnmet(String::from("1 > 2")));
+        roundtrip(&TestError::Crashed(String::from("killed by SIGSEGV")));
+        roundtrip(&TestError::DmesgFail(String::from("WARNING: CPU: 0")));
+        roundtrip(&TestError::DmesgWarn(String::from("BUG: oops")));
+        roundtrip(&TestError::FixtureFailed(String::from(
+            "connector: no connector",
+        )));
+        roundtrip(&std::io::Error::from_raw_os_error(22).into());
+        roundtrip(&TestError::NotEqual(
+            String::from("Ok(\n    (),\n)"),
+            String::from("3"),
+        ));
+        roundtrip(&TestError::Panicked(String::from("oops")));
+        roundtrip(&TestError::ResultNotError(String::from("Ok(())")));
+        roundtrip(&TestError::ResultNotOk(String::from("Err(())")));
+        roundtrip(&TestError::Skipped(String::from("requires vkms")));
+        roundtrip(&TestError::Timeout(Duration::from_millis(1500)));
+        roundtrip(&TestError::Unspecified);
+    }
+
+    fn isolated(f: fn() -> Result<(), TestError>, timeout: Duration) -> Result<(), TestError> {
+        let test = Test {
+            module_name: "cgt_core::isolation::tests",
+            test_name: "isolated",
+            test_fn: TestFunction::NoArg(f),
+            master: false,
+            client_capabilities: [None; 8],
+            required_capabilities: [None; 8],
+            driver: None,
+            min_version: None,
+            timeout: None,
+            fixtures: [None; 8],
+        };
+
+        run_test_isolated(
+            &test,
+            &TestDevice::open(Path::new("/dev/null")),
+            &Fixtures::default(),
+            Some(timeout),
+        )
+    }
+
+    #[test]
+    fn isolated_success() {
+        assert_eq!(isolated(|| Ok(()), TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn isolated_failure() {
+        assert_eq!(
+            isolated(
+                || Err(TestError::NotEqual(String::from("1"), String::from("2"))),
+                TIMEOUT
+            ),
+            Err(TestError::NotEqual(String::from("1"), String::from("2")))
+        );
+    }
+
+    #[test]
+    fn isolated_panic() {
+        let res = isolated(|| panic!("oops"), TIMEOUT);
+
+        assert!(matches!(
+            res,
+            Err(TestError::Panicked(ref msg)) if msg.starts_with("oops at ") && msg.contains("isolation.rs")
+        ));
+    }
+
+    #[test]
+    fn isolated_crash() {
+        let res = isolated(|| std::process::abort(), TIMEOUT);
+
+        assert!(matches!(res, Err(TestError::Crashed(ref msg)) if msg.contains("SIGABRT")));
+    }
+
+    #[test]
+    fn isolated_timeout() {
+        assert_eq!(
+            isolated(
+                || {
+                    thread::sleep(Duration::from_secs(10));
+                    Ok(())
+                },
+                Duration::from_millis(100)
+            ),
+            Err(TestError::Timeout(Duration::from_millis(100)))
+        );
+    }
+}
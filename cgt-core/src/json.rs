@@ -0,0 +1,288 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use drm_helpers::DriverVersion;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Expectation, KernelMessage, Taint, Test, TestError, TestOutcome, TestResultWriter, Verdict,
+};
+
+/// A single test result, as written on its own line by [`JsonResultWriter`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JsonRecord {
+    pub suite: String,
+    pub test: String,
+    pub outcome: TestOutcome,
+    pub error: Option<String>,
+    /// Test duration, in seconds
+    pub duration: f64,
+    pub device: Option<String>,
+    pub driver: Option<String>,
+    pub driver_version: Option<String>,
+    /// Kernel messages logged during the test, if the kernel log is captured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dmesg: Vec<String>,
+    /// Kernel taint flags that appeared during the test
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taint: Option<String>,
+    /// How the outcome compares to the expectation set for the test
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verdict: Option<Verdict>,
+    /// Comment of the expectation set for the test
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expectation: Option<String>,
+}
+
+/// Writes the results as JSON Lines, one [`JsonRecord`] per test.
+///
+/// Every record is written as soon as the test is done, so the output of an
+/// interrupted run is still usable.
+pub struct JsonResultWriter {
+    output: Box<dyn Write>,
+    device: Option<String>,
+    driver: Option<String>,
+    driver_version: Option<String>,
+    dmesg: Vec<String>,
+    taint: Option<String>,
+    verdict: Option<Verdict>,
+    expectation: Option<String>,
+}
+
+impl JsonResultWriter {
+    #[must_use]
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self {
+            output,
+            device: None,
+            driver: None,
+            driver_version: None,
+            dmesg: Vec::new(),
+            taint: None,
+            verdict: None,
+            expectation: None,
+        }
+    }
+
+    fn write_record(&mut self, record: &JsonRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.output, record)?;
+        writeln!(self.output)?;
+        self.output.flush()
+    }
+}
+
+impl TestResultWriter for JsonResultWriter {
+    fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    fn start_device(&mut self, device: &Path, version: Option<&DriverVersion>) {
+        self.device = Some(device.display().to_string());
+        self.driver = version.map(|version| version.name.clone());
+        self.driver_version =
+            version.map(|version| format!("{}.{}.{}", version.major, version.minor, version.patch));
+    }
+
+    fn write_test(&mut self, _test: &Test) {}
+
+    fn write_result(&mut self, test: &Test, res: &Result<(), TestError>, duration: Duration) {
+        let record = JsonRecord {
+            suite: test.module_name.to_string(),
+            test: test.test_name.to_string(),
+            outcome: res.into(),
+            error: match res {
+                Ok(()) => None,
+                Err(TestError::Skipped(reason)) => Some(reason.clone()),
+                Err(e) => Some(e.to_string()),
+            },
+            duration: duration.as_secs_f64(),
+            device: self.device.clone(),
+            driver: self.driver.clone(),
+            driver_version: self.driver_version.clone(),
+            dmesg: std::mem::take(&mut self.dmesg),
+            taint: self.taint.take(),
+            verdict: self.verdict.take(),
+            expectation: self.expectation.take(),
+        };
+
+        if let Err(e) = self.write_record(&record) {
+            eprintln!("Couldn't write the JSON record: {e}");
+        }
+    }
+
+    fn write_kernel_log(&mut self, _test: &Test, messages: &[KernelMessage]) {
+        self.dmesg = messages.iter().map(ToString::to_string).collect();
+    }
+
+    fn write_taint(&mut self, _test: &Test, taint: Taint) {
+        self.taint = Some(taint.to_string());
+    }
+
+    fn write_verdict(&mut self, _test: &Test, verdict: Verdict, expected: Option<&Expectation>) {
+        self.verdict = Some(verdict);
+        self.expectation = expected.and_then(|e| e.comment.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        tests::{test, write_run, write_verdicts, Buffer},
+        KernelMessage, TestError, TestOutcome, TestResultWriter, Verdict,
+    };
+
+    use super::{JsonRecord, JsonResultWriter};
+
+    #[test]
+    fn records() {
+        let buffer = Buffer::default();
+        let mut writer = JsonResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run(&mut writer);
+
+        let records: Vec<JsonRecord> = buffer
+            .contents()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 4);
+
+        for record in &records {
+            assert_eq!(record.suite, "cgt::tests::dummy");
+            assert_eq!(record.device.as_deref(), Some("/dev/dri/card0"));
+            assert_eq!(record.driver.as_deref(), Some("vkms"));
+            assert_eq!(record.driver_version.as_deref(), Some("1.0.0"));
+        }
+
+        assert_eq!(records[0].test, "test_pass");
+        assert_eq!(records[0].outcome, TestOutcome::Pass);
+        assert_eq!(records[0].error, None);
+        assert!((records[0].duration - 0.25).abs() < f64::EPSILON);
+
+        assert_eq!(records[1].test, "test_fail");
+        assert_eq!(records[1].outcome, TestOutcome::Fail);
+        assert_eq!(
+            records[1].error.as_deref(),
+            Some("Values 1 and 2 are not equal")
+        );
+
+        assert_eq!(records[2].outcome, TestOutcome::Skip);
+        assert_eq!(records[2].error.as_deref(), Some("requires <atomic>"));
+
+        assert_eq!(records[3].outcome, TestOutcome::Timeout);
+    }
+
+    #[test]
+    fn outcome_format() {
+        let buffer = Buffer::default();
+        let mut writer = JsonResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run(&mut writer);
+
+        assert!(buffer
+            .contents()
+            .starts_with(r#"{"suite":"cgt::tests::dummy","test":"test_pass","outcome":"pass","#));
+    }
+
+    #[test]
+    fn verdicts() {
+        let buffer = Buffer::default();
+        let mut writer = JsonResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_verdicts(&mut writer);
+
+        let records: Vec<JsonRecord> = buffer
+            .contents()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(records[0].verdict, Some(Verdict::Expected));
+        assert_eq!(
+            records[0].expectation.as_deref(),
+            Some("https://bugs.example/42")
+        );
+        assert_eq!(records[1].expectation, None);
+        assert!(buffer.contents().contains(r#""verdict":"expected""#));
+    }
+
+    #[test]
+    fn panic_and_crash() {
+        let buffer = Buffer::default();
+        let mut writer = JsonResultWriter::with_output(Box::new(buffer.clone()));
+        let test = test("cgt::tests::dummy", "test_abort");
+
+        writer.write_test(&test);
+        writer.write_result(
+            &test,
+            &Err(TestError::Panicked(String::from("oops"))),
+            Duration::ZERO,
+        );
+        writer.write_test(&test);
+        writer.write_result(
+            &test,
+            &Err(TestError::Crashed(String::from("SIGSEGV"))),
+            Duration::ZERO,
+        );
+
+        let records: Vec<JsonRecord> = buffer
+            .contents()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        // A panic is the test failing an assertion, only a signal is a crash.
+        assert_eq!(records[0].outcome, TestOutcome::Fail);
+        assert_eq!(records[1].outcome, TestOutcome::Crash);
+    }
+
+    #[test]
+    fn kernel_log() {
+        let buffer = Buffer::default();
+        let mut writer = JsonResultWriter::with_output(Box::new(buffer.clone()));
+        let test = test("cgt::tests::dummy", "test_warn");
+
+        writer.write_test(&test);
+        writer.write_kernel_log(
+            &test,
+            &[KernelMessage {
+                level: 4,
+                sequence: 42,
+                timestamp: Duration::from_micros(1_500_000),
+                message: String::from("WARNING: CPU: 0 PID: 42"),
+            }],
+        );
+        writer.write_result(
+            &test,
+            &Err(TestError::DmesgWarn(String::from(
+                "WARNING: CPU: 0 PID: 42",
+            ))),
+            Duration::ZERO,
+        );
+        writer.write_result(&test, &Ok(()), Duration::ZERO);
+
+        let contents = buffer.contents();
+        let records: Vec<JsonRecord> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(records[0].outcome, TestOutcome::Warn);
+        assert_eq!(
+            records[0].error.as_deref(),
+            Some("Kernel log reported a warning: WARNING: CPU: 0 PID: 42")
+        );
+        assert_eq!(
+            records[0].dmesg,
+            vec!["[    1.500000] WARNING: CPU: 0 PID: 42"]
+        );
+        assert!(records[1].dmesg.is_empty());
+        assert!(!contents.lines().nth(1).unwrap().contains("dmesg"));
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use drm_helpers::DriverVersion;
+
+use crate::{
+    Expectation, KernelMessage, Taint, Test, TestError, TestOutcome, TestResultWriter, Verdict,
+};
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Most control characters aren't allowed in XML, even escaped.
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn error_type(err: &TestError) -> &'static str {
+    match err {
+        TestError::ConditionUnmet(_) => "ConditionUnmet",
+        TestError::Crashed(_) => "Crashed",
+        TestError::DmesgFail(_) => "DmesgFail",
+        TestError::DmesgWarn(_) => "DmesgWarn",
+        TestError::FixtureFailed(_) => "FixtureFailed",
+        TestError::Io(_) => "Io",
+        TestError::NotEqual(_, _) => "NotEqual",
+        TestError::Panicked(_) => "Panicked",
+        TestError::ResultNotError(_) => "ResultNotError",
+        TestError::ResultNotOk(_) => "ResultNotOk",
+        TestError::Skipped(_) => "Skipped",
+        TestError::Timeout(_) => "Timeout",
+        TestError::Unspecified => "Unspecified",
+    }
+}
+
+fn seconds(time: Duration) -> String {
+    format!("{:.3}", time.as_secs_f64())
+}
+
+#[derive(Debug)]
+struct TestCase {
+    name: String,
+    time: Duration,
+    outcome: TestOutcome,
+    error_type: &'static str,
+    message: String,
+    kernel_log: Vec<String>,
+    properties: Vec<(&'static str, String)>,
+}
+
+#[derive(Debug)]
+struct TestSuite {
+    name: String,
+    device: String,
+    properties: Vec<(&'static str, String)>,
+    time: Duration,
+    cases: Vec<TestCase>,
+}
+
+impl TestSuite {
+    // Suites run against several devices share their name, so the device
+    // is needed to tell them apart.
+    fn full_name(&self, with_device: bool) -> String {
+        if with_device {
+            format!("{} ({})", self.name, self.device)
+        } else {
+            self.name.clone()
+        }
+    }
+
+    fn count(&self, outcome: TestOutcome) -> usize {
+        self.cases.iter().filter(|c| c.outcome == outcome).count()
+    }
+
+    fn failures(&self) -> usize {
+        self.count(TestOutcome::Fail)
+    }
+
+    fn errors(&self) -> usize {
+        self.count(TestOutcome::Crash) + self.count(TestOutcome::Timeout)
+    }
+
+    fn skipped(&self) -> usize {
+        self.count(TestOutcome::Skip)
+    }
+}
+
+/// Writes the results as a JUnit XML report, with one `<testsuite>` per test
+/// module and device.
+///
+/// The report is only written once the run is over, since the XML header
+/// needs to know about every test.
+pub struct JUnitResultWriter {
+    output: Box<dyn Write>,
+    device: String,
+    properties: Vec<(&'static str, String)>,
+    num_devices: usize,
+    suites: Vec<TestSuite>,
+    kernel_log: Vec<String>,
+    case_properties: Vec<(&'static str, String)>,
+}
+
+impl JUnitResultWriter {
+    #[must_use]
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self {
+            output,
+            device: String::new(),
+            properties: Vec::new(),
+            num_devices: 0,
+            suites: Vec::new(),
+            kernel_log: Vec::new(),
+            case_properties: Vec::new(),
+        }
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        let out = &mut self.output;
+        let suites = &self.suites;
+
+        let tests: usize = suites.iter().map(|s| s.cases.len()).sum();
+        let failures: usize = suites.iter().map(TestSuite::failures).sum();
+        let errors: usize = suites.iter().map(TestSuite::errors).sum();
+        let skipped: usize = suites.iter().map(TestSuite::skipped).sum();
+        let time: Duration = suites.iter().map(|s| s.time).sum();
+        let with_device = self.num_devices > 1;
+
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<testsuites name="cgt" tests="{tests}" failures="{failures}" errors="{errors}" skipped="{skipped}" time="{}">"#,
+            seconds(time)
+        )?;
+
+        for suite in suites {
+            let name = escape(&suite.full_name(with_device));
+
+            writeln!(
+                out,
+                r#"  <testsuite name="{name}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{}">"#,
+                suite.cases.len(),
+                suite.failures(),
+                suite.errors(),
+                suite.skipped(),
+                seconds(suite.time)
+            )?;
+
+            if !suite.properties.is_empty() {
+                writeln!(out, "    <properties>")?;
+
+                for (name, value) in &suite.properties {
+                    writeln!(
+                        out,
+                        r#"      <property name="{name}" value="{}"/>"#,
+                        escape(value)
+                    )?;
+                }
+
+                writeln!(out, "    </properties>")?;
+            }
+
+            for case in &suite.cases {
+                write!(
+                    out,
+                    r#"    <testcase name="{}" classname="{name}" time="{}""#,
+                    escape(&case.name),
+                    seconds(case.time)
+                )?;
+
+                if matches!(case.outcome, TestOutcome::Pass | TestOutcome::Warn)
+                    && case.kernel_log.is_empty()
+                    && case.properties.is_empty()
+                {
+                    writeln!(out, "/>")?;
+                    continue;
+                }
+
+                writeln!(out, ">")?;
+
+                if !case.properties.is_empty() {
+                    writeln!(out, "      <properties>")?;
+
+                    for (name, value) in &case.properties {
+                        writeln!(
+                            out,
+                            r#"        <property name="{name}" value="{}"/>"#,
+                            escape(value)
+                        )?;
+                    }
+
+                    writeln!(out, "      </properties>")?;
+                }
+
+                match case.outcome {
+                    TestOutcome::Pass | TestOutcome::Warn => {}
+                    TestOutcome::Skip => writeln!(
+                        out,
+                        r#"      <skipped message="{}"/>"#,
+                        escape(&case.message)
+                    )?,
+                    TestOutcome::Fail | TestOutcome::Crash | TestOutcome::Timeout => {
+                        let element = if case.outcome == TestOutcome::Fail {
+                            "failure"
+                        } else {
+                            "error"
+                        };
+
+                        writeln!(
+                            out,
+                            r#"      <{element} message="{0}" type="{1}">{0}</{element}>"#,
+                            escape(&case.message),
+                            case.error_type
+                        )?;
+                    }
+                }
+
+                if !case.kernel_log.is_empty() {
+                    writeln!(
+                        out,
+                        "      <system-err>{}</system-err>",
+                        escape(&case.kernel_log.join("\n"))
+                    )?;
+                }
+
+                writeln!(out, "    </testcase>")?;
+            }
+
+            writeln!(out, "  </testsuite>")?;
+        }
+
+        writeln!(out, "</testsuites>")?;
+
+        out.flush()
+    }
+}
+
+impl TestResultWriter for JUnitResultWriter {
+    fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    fn start_device(&mut self, device: &Path, version: Option<&DriverVersion>) {
+        self.num_devices += 1;
+        self.device = device.display().to_string();
+        self.properties = vec![("device", self.device.clone())];
+
+        if let Some(version) = version {
+            self.properties.extend([
+                ("driver", version.name.clone()),
+                (
+                    "driver.version",
+                    format!("{}.{}.{}", version.major, version.minor, version.patch),
+                ),
+                ("driver.date", version.date.clone()),
+                ("driver.description", version.desc.clone()),
+            ]);
+        }
+    }
+
+    fn start_suite(&mut self, name: &str, tests: &[Test]) {
+        self.suites.push(TestSuite {
+            name: name.to_string(),
+            device: self.device.clone(),
+            properties: self.properties.clone(),
+            time: Duration::ZERO,
+            cases: Vec::with_capacity(tests.len()),
+        });
+    }
+
+    fn write_test(&mut self, _test: &Test) {}
+
+    fn write_result(&mut self, test: &Test, res: &Result<(), TestError>, duration: Duration) {
+        let (error_type, message) = match res {
+            Ok(()) => ("", String::new()),
+            Err(TestError::Skipped(reason)) => ("Skipped", reason.clone()),
+            Err(e) => (error_type(e), e.to_string()),
+        };
+
+        if let Some(suite) = self.suites.last_mut() {
+            suite.cases.push(TestCase {
+                name: test.test_name.to_string(),
+                time: duration,
+                outcome: res.into(),
+                error_type,
+                message,
+                kernel_log: std::mem::take(&mut self.kernel_log),
+                properties: std::mem::take(&mut self.case_properties),
+            });
+        }
+    }
+
+    fn write_kernel_log(&mut self, _test: &Test, messages: &[KernelMessage]) {
+        self.kernel_log = messages.iter().map(ToString::to_string).collect();
+    }
+
+    fn write_taint(&mut self, _test: &Test, taint: Taint) {
+        self.kernel_log.push(format!("Kernel tainted: {taint}"));
+    }
+
+    // Only tests with an expectation, or failing unexpectedly, get a verdict,
+    // the others would all be "expected".
+    fn write_verdict(&mut self, _test: &Test, verdict: Verdict, expected: Option<&Expectation>) {
+        if expected.is_none() && verdict == Verdict::Expected {
+            return;
+        }
+
+        self.case_properties = vec![("verdict", verdict.as_str().to_string())];
+
+        if let Some(comment) = expected.and_then(|e| e.comment.clone()) {
+            self.case_properties.push(("expectation", comment));
+        }
+    }
+
+    fn end_suite(&mut self, duration: Duration) {
+        if let Some(suite) = self.suites.last_mut() {
+            suite.time = duration;
+        }
+    }
+
+    fn end_run(&mut self) {
+        if let Err(e) = self.write_report() {
+            eprintln!("Couldn't write the JUnit report: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{write_run, write_run_on, write_verdicts, Buffer};
+
+    use super::{escape, JUnitResultWriter};
+
+    #[test]
+    fn escape_special_characters() {
+        assert_eq!(
+            escape("<a href=\"x\">&'\u{1}</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&apos;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn report() {
+        let buffer = Buffer::default();
+        let mut writer = JUnitResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run(&mut writer);
+
+        let report = buffer.contents();
+
+        assert!(report.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(report.contains(
+            r#"<testsuite name="cgt::tests::dummy" tests="4" failures="1" errors="1" skipped="1" time="1.270">"#
+        ));
+        assert!(report.contains(
+            r#"<testsuites name="cgt" tests="4" failures="1" errors="1" skipped="1" time="1.270">"#
+        ));
+        assert!(report.contains(r#"<property name="driver" value="vkms"/>"#));
+        assert!(report.contains(r#"<property name="driver.version" value="1.0.0"/>"#));
+        assert!(report.contains(
+            r#"<testcase name="test_pass" classname="cgt::tests::dummy" time="0.250"/>"#
+        ));
+        assert!(
+            report.contains(r#"<failure message="Values 1 and 2 are not equal" type="NotEqual">"#)
+        );
+        assert!(report.contains(r#"<skipped message="requires &lt;atomic&gt;"/>"#));
+        assert!(report.contains(r#"<error message="Test timed out after 1s" type="Timeout">"#));
+        assert!(report.ends_with("</testsuites>\n"));
+    }
+
+    #[test]
+    fn verdicts() {
+        let buffer = Buffer::default();
+        let mut writer = JUnitResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_verdicts(&mut writer);
+
+        let report = buffer.contents();
+
+        assert!(report.contains(
+            r#"<property name="verdict" value="expected"/>
+        <property name="expectation" value="https://bugs.example/42"/>
+      </properties>
+      <failure message="Values 1 and 2 are not equal""#
+        ));
+        assert!(report.contains(
+            r#"<testcase name="test_flaky" classname="cgt::tests::dummy" time="0.000">
+      <properties>
+        <property name="verdict" value="expected"/>
+      </properties>"#
+        ));
+        assert!(report.contains(
+            r#"<testcase name="test_pass" classname="cgt::tests::dummy" time="0.000"/>"#
+        ));
+    }
+
+    #[test]
+    fn several_devices() {
+        let buffer = Buffer::default();
+        let mut writer = JUnitResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run_on(&mut writer, &["/dev/dri/card0", "/dev/dri/card1"]);
+
+        let report = buffer.contents();
+
+        assert!(report.contains(
+            r#"<testsuites name="cgt" tests="8" failures="2" errors="2" skipped="2" time="2.540">"#
+        ));
+        assert!(
+            report.contains(r#"<testsuite name="cgt::tests::dummy (/dev/dri/card0)" tests="4""#)
+        );
+        assert!(
+            report.contains(r#"<testsuite name="cgt::tests::dummy (/dev/dri/card1)" tests="4""#)
+        );
+        assert!(report.contains(r#"<property name="device" value="/dev/dri/card1"/>"#));
+        assert!(report.contains(
+            r#"<testcase name="test_pass" classname="cgt::tests::dummy (/dev/dri/card1)" time="0.250"/>"#
+        ));
+    }
+}
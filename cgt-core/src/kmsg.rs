@@ -0,0 +1,254 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use nix::fcntl::OFlag;
+
+pub const DEFAULT_KMSG_PATH: &str = "/dev/kmsg";
+
+// Log levels, as found in the lower bits of the record priority
+const LOG_ERR: u8 = 3;
+const LOG_WARNING: u8 = 4;
+
+// /dev/kmsg returns a single record per read, and fails if the buffer is too
+// small to hold it.
+const RECORD_MAX_LEN: usize = 8192;
+
+/// What to do with a test that succeeded but left a warning or an error in
+/// the kernel log.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KernelLogPolicy {
+    /// Only attach the kernel log to the result
+    #[default]
+    Ignore,
+
+    /// Report the test with a warning outcome
+    Warn,
+
+    /// Report the test as a failure
+    Fail,
+}
+
+impl FromStr for KernelLogPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "warn" => Ok(Self::Warn),
+            "fail" => Ok(Self::Fail),
+            _ => Err(format!("Unknown policy {s}, expected ignore, warn or fail")),
+        }
+    }
+}
+
+/// A single record from the kernel log
+#[derive(Clone, Debug, PartialEq)]
+pub struct KernelMessage {
+    pub level: u8,
+    pub sequence: u64,
+    pub timestamp: Duration,
+    pub message: String,
+}
+
+impl KernelMessage {
+    // Records are formatted as "<prio>,<seq>,<timestamp>,<flags>[,...];<msg>"
+    fn parse(line: &str) -> Option<Self> {
+        let (header, message) = line.split_once(';')?;
+        let mut fields = header.split(',');
+
+        let prio: u32 = fields.next()?.parse().ok()?;
+        let sequence = fields.next()?.parse().ok()?;
+        let timestamp = fields.next()?.parse().ok()?;
+
+        Some(Self {
+            level: u8::try_from(prio & 7).ok()?,
+            sequence,
+            timestamp: Duration::from_micros(timestamp),
+            message: message.to_string(),
+        })
+    }
+
+    /// Whether the message is an error, or a kernel warning or bug splat.
+    #[must_use]
+    pub fn is_warning(&self) -> bool {
+        self.level <= LOG_ERR
+            || (self.level <= LOG_WARNING
+                && (self.message.starts_with("WARNING:") || self.message.starts_with("BUG:")))
+    }
+}
+
+impl fmt::Display for KernelMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:5}.{:06}] {}",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_micros(),
+            self.message
+        )
+    }
+}
+
+/// Reader for the kernel log, that returns the messages logged since it was
+/// last read.
+#[derive(Debug)]
+pub struct KernelLog {
+    file: File,
+    partial: Vec<u8>,
+}
+
+impl KernelLog {
+    /// Opens the kernel log at `path`, usually [`DEFAULT_KMSG_PATH`], and
+    /// skips all the messages already in there.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`std::io::Error`] if the log can't be opened or seeked.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(path)?;
+
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            file,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Returns all the messages logged since the last call.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`std::io::Error`] if the log can't be read.
+    pub fn read(&mut self) -> io::Result<Vec<KernelMessage>> {
+        let mut buf = vec![0; RECORD_MAX_LEN];
+
+        loop {
+            match self.file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => self.partial.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // Some records have been overwritten since our last read,
+                // the next one will be the oldest still around.
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let complete = match self.partial.iter().rposition(|&b| b == b'\n') {
+            Some(pos) => self.partial.drain(..=pos).collect::<Vec<_>>(),
+            None => return Ok(Vec::new()),
+        };
+
+        // Lines starting with a space hold the record metadata, we don't
+        // need those.
+        Ok(String::from_utf8_lossy(&complete)
+            .lines()
+            .filter(|line| !line.starts_with(' '))
+            .filter_map(KernelMessage::parse)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write, path::Path, time::Duration};
+
+    use crate::tests::TempPath;
+
+    use super::{KernelLog, KernelMessage};
+
+    fn kmsg_file(name: &str) -> TempPath {
+        let path = TempPath::new(&format!("kmsg-{name}"));
+
+        std::fs::write(&path, "6,1,100,-;previous run\n").unwrap();
+
+        path
+    }
+
+    fn append(path: &Path, content: &str) {
+        OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            KernelMessage::parse("4,1234,5678901,-,caller=T42;WARNING: CPU: 0 PID: 42"),
+            Some(KernelMessage {
+                level: 4,
+                sequence: 1234,
+                timestamp: Duration::from_micros(5_678_901),
+                message: String::from("WARNING: CPU: 0 PID: 42"),
+            })
+        );
+
+        // Facility bits are ignored
+        assert_eq!(KernelMessage::parse("11,1,0,-;err").unwrap().level, 3);
+        assert_eq!(KernelMessage::parse("garbage"), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            KernelMessage::parse("6,1,5678901,-;hello")
+                .unwrap()
+                .to_string(),
+            "[    5.678901] hello"
+        );
+    }
+
+    #[test]
+    fn warnings() {
+        let warning = |line| KernelMessage::parse(line).unwrap().is_warning();
+
+        assert!(warning(
+            "4,1,0,-;WARNING: CPU: 0 PID: 1 at drivers/gpu/drm/drm_atomic.c:42"
+        ));
+        assert!(warning("1,1,0,-;BUG: kernel NULL pointer dereference"));
+        assert!(warning("3,1,0,-;[drm] *ERROR* flip_done timed out"));
+        assert!(!warning("4,1,0,-;[drm] Something fishy"));
+        assert!(!warning(
+            "6,1,0,-;WARNING: only mentioned at the info level"
+        ));
+    }
+
+    #[test]
+    fn read_new_messages() {
+        let path = kmsg_file("read");
+        let mut log = KernelLog::open(&path).unwrap();
+
+        assert_eq!(log.read().unwrap(), Vec::new());
+
+        append(
+            &path,
+            "6,2,200,-;first\n SUBSYSTEM=drm\n4,3,300,-;WARNING: second\n6,4,400,-;trun",
+        );
+
+        let messages = log.read().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].message, "first");
+        assert_eq!(messages[1].message, "WARNING: second");
+
+        append(&path, "cated\n");
+
+        let messages = log.read().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message, "truncated");
+    }
+}
@@ -1,34 +1,90 @@
 use std::{
-    collections::HashMap,
+    any::Any,
+    cell::RefCell,
     fs::File,
-    os::fd::{AsFd, AsRawFd, BorrowedFd},
+    io,
+    os::fd::{AsFd, BorrowedFd},
+    panic::{self, AssertUnwindSafe},
     path::{Path, PathBuf},
     process::{ExitCode, Termination},
+    sync::{mpsc, Once},
+    thread,
+    time::{Duration, Instant},
 };
 
-use drm_helpers::{set_client_capability, set_master};
-use glob::glob;
+use drm_helpers::{
+    drop_master, get_capability, get_version, is_master, ClientStateGuard, DriverVersion,
+};
+pub use glob::Pattern;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use drm_uapi::{drm_ioctl_version, drm_version, ClientCapability};
+use drm_uapi::{ClientCapability, DriverCapability};
+
+mod compare;
+mod device;
+mod expectations;
+mod fixture;
+mod igt;
+mod isolation;
+mod json;
+mod junit;
+mod kmsg;
+mod taint;
+mod tap;
+
+pub use compare::{compare_results, read_json_results, Comparison, OutcomeChange, ResultsError};
+use device::find_devices;
+pub use device::{Candidate, DeviceError, DeviceFinder, DeviceSpecifier};
+pub use expectations::{Expectation, Expectations, ExpectationsError, ExpectedOutcome, Verdict};
+pub use fixture::{fixture, Fixture, FixtureScope, FixtureTeardown, FixtureValue};
+use fixture::{setup_fixtures, FixtureSet, Fixtures};
+pub use igt::{igt_test_name, IgtResultWriter};
+use isolation::run_test_isolated;
+pub use json::{JsonRecord, JsonResultWriter};
+pub use junit::JUnitResultWriter;
+pub use kmsg::{KernelLog, KernelLogPolicy, KernelMessage, DEFAULT_KMSG_PATH};
+pub use taint::{Taint, TaintMonitor, DEFAULT_TAINT_PATH};
+pub use tap::TapResultWriter;
 
 #[derive(Debug, Error)]
 pub enum TestError {
     #[error("Condition {0} is not true")]
     ConditionUnmet(String),
 
+    #[error("Test crashed: {0}")]
+    Crashed(String),
+
+    #[error("Kernel log reported a failure: {0}")]
+    DmesgFail(String),
+
+    #[error("Kernel log reported a warning: {0}")]
+    DmesgWarn(String),
+
+    #[error("Fixture failed: {0}")]
+    FixtureFailed(String),
+
     #[error("I/O Error")]
     Io(#[from] std::io::Error),
 
     #[error("Values {0} and {1} are not equal")]
     NotEqual(String, String),
 
+    #[error("Test panicked: {0}")]
+    Panicked(String),
+
     #[error("Result {0} isn't an error")]
     ResultNotError(String),
 
     #[error("Result {0} isn't a value")]
     ResultNotOk(String),
 
+    #[error("Skipped: {0}")]
+    Skipped(String),
+
+    #[error("Test timed out after {0:?}")]
+    Timeout(Duration),
+
     #[error("Unknown Error")]
     Unspecified,
 }
@@ -37,25 +93,66 @@ impl PartialEq for TestError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::ConditionUnmet(l0), Self::ConditionUnmet(r0)) => l0 == r0,
+            (Self::Crashed(l0), Self::Crashed(r0)) => l0 == r0,
+            (Self::DmesgFail(l0), Self::DmesgFail(r0)) => l0 == r0,
+            (Self::DmesgWarn(l0), Self::DmesgWarn(r0)) => l0 == r0,
+            (Self::FixtureFailed(l0), Self::FixtureFailed(r0)) => l0 == r0,
             (Self::Io(l0), Self::Io(r0)) => l0.raw_os_error() == r0.raw_os_error(),
             (Self::NotEqual(l0, l1), Self::NotEqual(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Panicked(l0), Self::Panicked(r0)) => l0 == r0,
             (Self::ResultNotError(l0), Self::ResultNotError(r0)) => l0 == r0,
             (Self::ResultNotOk(l0), Self::ResultNotOk(r0)) => l0 == r0,
+            (Self::Skipped(l0), Self::Skipped(r0)) => l0 == r0,
+            (Self::Timeout(l0), Self::Timeout(r0)) => l0 == r0,
             (Self::Unspecified, Self::Unspecified) => true,
             _ => core::mem::discriminant(self) == core::mem::discriminant(other),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestOutcome {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+    Crash,
+    Timeout,
+}
+
+impl TestOutcome {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestOutcome::Pass => "pass",
+            TestOutcome::Warn => "warn",
+            TestOutcome::Fail => "fail",
+            TestOutcome::Skip => "skip",
+            TestOutcome::Crash => "crash",
+            TestOutcome::Timeout => "timeout",
+        }
+    }
+}
+
+impl From<&Result<(), TestError>> for TestOutcome {
+    fn from(value: &Result<(), TestError>) -> Self {
+        match value {
+            Ok(()) => TestOutcome::Pass,
+            Err(TestError::Skipped(_)) => TestOutcome::Skip,
+            Err(TestError::DmesgWarn(_)) => TestOutcome::Warn,
+            Err(TestError::Crashed(_)) => TestOutcome::Crash,
+            Err(TestError::Timeout(_)) => TestOutcome::Timeout,
+            Err(_) => TestOutcome::Fail,
+        }
+    }
+}
+
 impl From<nix::Error> for TestError {
     fn from(value: nix::Error) -> Self {
-        let io_err: Result<std::io::Error, _> = value.try_into();
+        let err: std::io::Error = value.into();
 
-        if let Ok(err) = io_err {
-            err.into()
-        } else {
-            TestError::Unspecified
-        }
+        err.into()
     }
 }
 
@@ -73,82 +170,297 @@ pub struct Test {
     pub test_fn: TestFunction,
     pub master: bool,
     pub client_capabilities: [Option<ClientCapability>; 8],
+    pub required_capabilities: [Option<DriverCapability>; 8],
+    pub driver: Option<&'static str>,
+    pub min_version: Option<(i32, i32, i32)>,
+    pub timeout: Option<Duration>,
+
+    /// Names of the fixtures of the module the test uses
+    pub fixtures: [Option<&'static str>; 8],
+}
+
+impl Test {
+    #[must_use]
+    pub fn full_name(&self) -> String {
+        format!("{}::{}", self.module_name, self.test_name)
+    }
 }
 
 pub trait TestResultWriter {
     fn new() -> Self;
     fn write_test(&mut self, test: &Test);
-    fn write_result(&mut self, test: &Test, res: &Result<(), TestError>);
 
+    /// Called once a test is done, with its result and how long it took to
+    /// run, as measured on a monotonic clock.
+    fn write_result(&mut self, test: &Test, res: &Result<(), TestError>, duration: Duration);
+
+    /// Called right before [`TestResultWriter::write_result`] with the kernel
+    /// messages logged while the test was running, if the kernel log is
+    /// captured.
+    fn write_kernel_log(&mut self, _test: &Test, _messages: &[KernelMessage]) {}
+
+    /// Called right before [`TestResultWriter::write_result`] if the kernel
+    /// got tainted while the test was running, with the new taint flags.
+    fn write_taint(&mut self, _test: &Test, _taint: Taint) {}
+
+    /// Called right before [`TestResultWriter::write_result`] with how the
+    /// result compares to the expectation set for the test, if any.
+    fn write_verdict(&mut self, _test: &Test, _verdict: Verdict, _expected: Option<&Expectation>) {}
+
+    /// Called instead of [`TestResultWriter::start_run`] if the devices to
+    /// test couldn't be found.
+    fn write_device_error(&mut self, err: &DeviceError) {
+        eprintln!("{err}");
+    }
+
+    /// Called instead of [`TestResultWriter::start_run`] if the kernel log
+    /// or taint monitor requested couldn't be set up.
+    fn write_run_error(&mut self, err: &RunError) {
+        eprintln!("{err}");
+    }
+
+    /// Called whenever something goes wrong in the run itself, rather than
+    /// in a test. Issues found while a test runs are reported before its
+    /// result.
+    fn write_warning(&mut self, warning: &RunWarning) {
+        eprintln!("{warning}");
+    }
+
+    fn start_run(&mut self) {}
+
+    /// Called before running the tests against each device. `version` is
+    /// `None` if the driver couldn't be queried.
+    fn start_device(&mut self, _device: &Path, _version: Option<&DriverVersion>) {}
     fn start_suite(&mut self, _name: &str, _tests: &[Test]) {}
-    fn end_suite(&mut self) {}
+
+    /// Called once all the tests of a suite are done, with the time it took
+    /// to run all of them.
+    fn end_suite(&mut self, _duration: Duration) {}
+    fn end_device(&mut self) {}
+    fn end_run(&mut self) {}
 }
 
 inventory::collect!(Test);
 
-fn get_test_suites() -> HashMap<String, Vec<Test>> {
-    let mut map = HashMap::new();
+#[derive(Clone, Debug, Default)]
+pub struct TestSelection {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    names: Vec<String>,
+    seed: Option<u64>,
+}
+
+impl TestSelection {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn include(mut self, pattern: Pattern) -> Self {
+        self.include.push(pattern);
+        self
+    }
+
+    #[must_use]
+    pub fn exclude(mut self, pattern: Pattern) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
 
-    for test in inventory::iter::<Test> {
-        if !map.contains_key(test.module_name) {
-            map.insert(test.module_name.to_string(), Vec::new());
+    #[must_use]
+    pub fn name(mut self, name: &str) -> Self {
+        self.names.push(name.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn shuffle(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    #[must_use]
+    pub fn matches(&self, test: &Test) -> bool {
+        let name = test.full_name();
+
+        if self.exclude.iter().any(|p| p.matches(&name)) {
+            return false;
+        }
+
+        // Without any include pattern or name, everything is selected.
+        if self.include.is_empty() && self.names.is_empty() {
+            return true;
         }
 
-        map.get_mut(test.module_name).unwrap().push(test.clone());
+        self.names.contains(&name) || self.include.iter().any(|p| p.matches(&name))
+    }
+}
+
+// SplitMix64. We don't need anything fancy, but we need a given seed to
+// always produce the same order so that a shuffled run can be replayed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
     }
 
-    map
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = usize::try_from(self.next() % (i as u64 + 1)).unwrap();
+
+            items.swap(i, j);
+        }
+    }
 }
 
-pub enum DeviceSpecifier {
-    ModuleName(String),
-    Path(PathBuf),
+fn group_by_suite(tests: Vec<Test>) -> Vec<(String, Vec<Test>)> {
+    let mut suites: Vec<(String, Vec<Test>)> = Vec::new();
+
+    for test in tests {
+        match suites.last_mut() {
+            Some((name, tests)) if name == test.module_name => tests.push(test),
+            _ => suites.push((test.module_name.to_string(), vec![test])),
+        }
+    }
+
+    suites
 }
 
-fn find_device(dev: DeviceSpecifier) -> Result<PathBuf, TestError> {
-    match dev {
-        DeviceSpecifier::ModuleName(module) => {
-            for entry in glob("/dev/dri/card*").expect("Failed to read glob pattern") {
-                match entry {
-                    Ok(ref path) => {
-                        let f = File::open(path)?;
+fn shuffle_tests(tests: Vec<Test>, seed: u64) -> Vec<Test> {
+    let mut rng = Rng(seed);
+    let mut suites = group_by_suite(tests);
 
-                        let mut count = drm_version::default();
+    rng.shuffle(&mut suites);
 
-                        unsafe { drm_ioctl_version(f.as_fd().as_raw_fd(), &mut count) }?;
+    for (_, tests) in &mut suites {
+        rng.shuffle(tests);
+    }
 
-                        let mut name: Vec<u8> = Vec::with_capacity(count.name_len);
+    suites.into_iter().flat_map(|(_, tests)| tests).collect()
+}
 
-                        let mut data = drm_version {
-                            name_len: count.name_len,
-                            name: name.as_mut_ptr() as u64,
+#[must_use]
+pub fn list_tests(selection: &TestSelection) -> Vec<Test> {
+    let mut tests: Vec<Test> = inventory::iter::<Test>
+        .into_iter()
+        .filter(|test| selection.matches(test))
+        .cloned()
+        .collect();
 
-                            ..Default::default()
-                        };
+    tests.sort_by_key(|test| (test.module_name, test.test_name));
 
-                        unsafe {
-                            drm_ioctl_version(f.as_fd().as_raw_fd(), &mut data)?;
-                            name.set_len(data.name_len);
-                        };
+    match selection.seed {
+        Some(seed) => shuffle_tests(tests, seed),
+        None => tests,
+    }
+}
 
-                        if String::from_utf8_lossy(&name) == module {
-                            return Ok(path.clone());
-                        }
-                    }
+fn get_test_suites(selection: &TestSelection) -> Vec<(String, Vec<Test>)> {
+    group_by_suite(list_tests(selection))
+}
 
-                    Err(e) => return Err(e.into_error().into()),
-                }
+fn check_requirements(fd: BorrowedFd<'_>, test: &Test) -> Result<(), TestError> {
+    if test.driver.is_some() || test.min_version.is_some() {
+        let version = get_version(fd)?;
+
+        if let Some(driver) = test.driver {
+            if version.name != driver {
+                return Err(TestError::Skipped(format!(
+                    "requires the {driver} driver, running on {}",
+                    version.name
+                )));
+            }
+        }
+
+        if let Some((major, minor, patch)) = test.min_version {
+            if (version.major, version.minor, version.patch) < (major, minor, patch) {
+                return Err(TestError::Skipped(format!(
+                    "requires driver version {major}.{minor}.{patch}, running on {}.{}.{}",
+                    version.major, version.minor, version.patch
+                )));
             }
+        }
+    }
 
-            Err(nix::errno::Errno::ENODEV.into())
+    for cap in test.required_capabilities.into_iter().flatten() {
+        // Older kernels reject capabilities they don't know about with
+        // EINVAL, which means the driver doesn't support it either. Any
+        // other error is a problem with the device itself.
+        let value = match get_capability(fd, cap) {
+            Ok(value) => value,
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        if value == 0 {
+            return Err(TestError::Skipped(format!(
+                "requires the {cap:?} capability"
+            )));
         }
-        DeviceSpecifier::Path(p) => Ok(p),
     }
+
+    Ok(())
+}
+
+/// Why a run couldn't start, besides not finding the device to test
+#[derive(Debug, Error)]
+pub enum RunError {
+    #[error("Couldn't open the kernel log {}: {source}", .path.display())]
+    KernelLog { path: PathBuf, source: io::Error },
+
+    #[error("Couldn't read the kernel taint {}: {source}", .path.display())]
+    Taint { path: PathBuf, source: io::Error },
+}
+
+/// Issue with the run that doesn't stop it, but that the results might need
+/// to be read in the light of
+#[derive(Debug, Error)]
+pub enum RunWarning {
+    #[error("Couldn't read the kernel log: {0}")]
+    KernelLog(io::Error),
+
+    #[error("Couldn't read the kernel taint: {0}")]
+    Taint(io::Error),
+
+    #[error("Kernel already tainted: {0}")]
+    AlreadyTainted(Taint),
+
+    #[error("Kernel tainted by {0}, aborting the run")]
+    AbortedOnTaint(String),
+
+    #[error("{0}, outside of any test")]
+    StrayKernelLog(TestError),
+
+    #[error("Kernel tainted outside of any test: {0}")]
+    StrayTaint(Taint),
+
+    #[error("{0} timed out and can't be stopped without isolation, aborting the run")]
+    TestAbandoned(String),
+
+    #[error("Fixture setup for {0} got stuck, skipping the rest of the suite")]
+    FixtureStuck(String),
+
+    #[error("{0}")]
+    Fixture(TestError),
 }
 
 pub enum RunResult {
     Success,
     Failure,
+
+    /// The device to test couldn't be found, so no test ran
+    DeviceError,
+
+    /// The run couldn't start for another reason, so no test ran, or the
+    /// results to compare couldn't be read
+    Error,
 }
 
 impl<U, E> From<Result<U, E>> for RunResult {
@@ -165,48 +477,1190 @@ impl Termination for RunResult {
         match self {
             RunResult::Success => ExitCode::SUCCESS,
             RunResult::Failure => ExitCode::FAILURE,
+            RunResult::DeviceError => ExitCode::from(3),
+            RunResult::Error => ExitCode::from(4),
+        }
+    }
+}
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    /// Run each test in its own forked process, so that a crashing test
+    /// doesn't take the whole run down with it.
+    pub isolate: bool,
+
+    /// Time after which a test that doesn't set its own timeout is
+    /// considered stuck. Isolated tests are killed. Other tests can't be
+    /// stopped and might hold on to the device, so the run is aborted.
+    /// Setting up the fixtures of a test is bound by the same timeout.
+    pub timeout: Option<Duration>,
+
+    /// Kernel log to capture the messages of each test from, usually
+    /// [`DEFAULT_KMSG_PATH`].
+    pub kernel_log: Option<PathBuf>,
+
+    /// How to report a test that left warnings in the kernel log
+    pub kernel_log_policy: KernelLogPolicy,
+
+    /// Kernel taint mask to check between tests, usually
+    /// [`DEFAULT_TAINT_PATH`].
+    pub taint: Option<PathBuf>,
+
+    /// Stop the run as soon as a test taints the kernel
+    pub abort_on_taint: bool,
+
+    /// Known issues of the driver. Only the results that don't match them
+    /// make the run fail.
+    pub expectations: Expectations,
+
+    /// Run the tests against every device matching the specifier, one after
+    /// the other, instead of only the first one.
+    pub all_devices: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            isolate: false,
+            timeout: Some(DEFAULT_TIMEOUT),
+            kernel_log: None,
+            kernel_log_policy: KernelLogPolicy::default(),
+            taint: None,
+            abort_on_taint: false,
+            expectations: Expectations::default(),
+            all_devices: false,
+        }
+    }
+}
+
+fn check_kernel_log(
+    res: Result<(), TestError>,
+    messages: &[KernelMessage],
+    policy: KernelLogPolicy,
+) -> Result<(), TestError> {
+    res?;
+
+    let Some(warning) = messages.iter().find(|msg| msg.is_warning()) else {
+        return Ok(());
+    };
+
+    match policy {
+        KernelLogPolicy::Ignore => Ok(()),
+        KernelLogPolicy::Warn => Err(TestError::DmesgWarn(warning.message.clone())),
+        KernelLogPolicy::Fail => Err(TestError::DmesgFail(warning.message.clone())),
+    }
+}
+
+/// Device a test runs against. Every test gets a file of its own, so that
+/// nothing a test leaves behind on its file can leak into the next ones.
+/// Tests wanting to share a file do so through a suite fixture owning it.
+pub(crate) struct TestDevice {
+    path: PathBuf,
+    file: io::Result<File>,
+}
+
+impl TestDevice {
+    pub(crate) fn open(path: &Path) -> Self {
+        let file = File::open(path).inspect(|file| {
+            // Opening the device might have made us master, which only the
+            // tests asking for it should be.
+            if is_master(file.as_fd()).unwrap_or(false) {
+                let _ = drop_master(file.as_fd());
+            }
+        });
+
+        Self {
+            path: path.to_path_buf(),
+            file,
+        }
+    }
+
+    // For a test running on another thread, with the same file.
+    fn try_clone(&self) -> Self {
+        let file = match self.file {
+            Ok(ref file) => file.try_clone(),
+            Err(ref e) => Err(io::Error::new(e.kind(), e.to_string())),
+        };
+
+        Self {
+            path: self.path.clone(),
+            file,
+        }
+    }
+
+    // Every test taking the file gets its own copy of the error.
+    fn fd(&self) -> Result<BorrowedFd<'_>, TestError> {
+        match self.file {
+            Ok(ref file) => Ok(file.as_fd()),
+            Err(ref e) => Err(io::Error::new(e.kind(), e.to_string()).into()),
         }
     }
 }
 
-pub fn run_all(writer: &mut impl TestResultWriter, dev: DeviceSpecifier) -> RunResult {
-    let mut result = Ok(());
+fn run_test(test: &Test, device: &TestDevice, fixtures: &Fixtures) -> Result<(), TestError> {
+    fixtures.with(|| match test.test_fn {
+        TestFunction::NoArg(f) => f(),
+        TestFunction::WithFd(f) => device.fd().and_then(f),
+        TestFunction::WithPath(f) => f(&device.path),
+    })
+}
+
+// Tests that can't run on the device are skipped before setting anything up
+// for them.
+fn check_device_requirements(test: &Test, device: &TestDevice) -> Result<(), TestError> {
+    match test.test_fn {
+        TestFunction::WithFd(_) => check_requirements(device.fd()?, test),
+        TestFunction::NoArg(_) | TestFunction::WithPath(_) => Ok(()),
+    }
+}
+
+fn client_state<'a>(
+    test: &Test,
+    fd: BorrowedFd<'a>,
+) -> Result<ClientStateGuard<BorrowedFd<'a>>, TestError> {
+    let mut state = ClientStateGuard::new(fd);
+
+    if test.master {
+        state.set_master()?;
+    }
+
+    for cap in test.client_capabilities.into_iter().flatten() {
+        state.set_client_capability(cap)?;
+    }
+
+    Ok(state)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        (*msg).to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
+thread_local! {
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+// The panic payload doesn't tell us where the panic happened, so we need a
+// hook to record it before the stack unwinds. The previous hook still runs
+// so the panic is printed as usual.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let prev = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            PANIC_LOCATION.with(|loc| *loc.borrow_mut() = info.location().map(ToString::to_string));
+            prev(info);
+        }));
+    });
+}
+
+fn run_test_catch_unwind(
+    test: &Test,
+    device: &TestDevice,
+    fixtures: &Fixtures,
+) -> Result<(), TestError> {
+    install_panic_hook();
+    PANIC_LOCATION.with(|loc| loc.borrow_mut().take());
+
+    panic::catch_unwind(AssertUnwindSafe(|| run_test(test, device, fixtures))).unwrap_or_else(
+        |payload| {
+            let msg = panic_message(payload.as_ref());
+
+            Err(TestError::Panicked(
+                match PANIC_LOCATION.with(|loc| loc.borrow_mut().take()) {
+                    Some(location) => format!("{msg} at {location}"),
+                    None => msg,
+                },
+            ))
+        },
+    )
+}
+
+// A test running in our process can't be stopped, so once the timeout
+// expires we stop waiting for it and leave it running on its own thread.
+fn run_test_with_timeout(
+    test: &Test,
+    device: &TestDevice,
+    fixtures: &Fixtures,
+    timeout: Option<Duration>,
+) -> Result<(), TestError> {
+    let Some(timeout) = timeout else {
+        return run_test_catch_unwind(test, device, fixtures);
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let thread_test = test.clone();
+    let thread_device = device.try_clone();
+    let thread_fixtures = fixtures.clone();
+
+    thread::spawn(move || {
+        let _ = tx.send(run_test_catch_unwind(
+            &thread_test,
+            &thread_device,
+            &thread_fixtures,
+        ));
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(res) => res,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(TestError::Timeout(timeout)),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(TestError::Unspecified),
+    }
+}
+
+// The master status and client capabilities of the device file are set up
+// here rather than by the test itself, so that they're undone even if the
+// test process crashed or had to be killed: it shares the file with us.
+fn run_test_on_device(
+    test: &Test,
+    device: &TestDevice,
+    fixtures: &Fixtures,
+    isolate: bool,
+    timeout: Option<Duration>,
+) -> Result<(), TestError> {
+    let mut state = match test.test_fn {
+        TestFunction::WithFd(_) => Some(client_state(test, device.fd()?)?),
+        TestFunction::NoArg(_) | TestFunction::WithPath(_) => None,
+    };
+
+    let res = if isolate {
+        run_test_isolated(test, device, fixtures, timeout)
+    } else {
+        run_test_with_timeout(test, device, fixtures, timeout)
+    };
+
+    // The following tests would run with whatever we couldn't undo.
+    match state.as_mut().map(ClientStateGuard::restore) {
+        Some(Err(e)) => res.and(Err(e.into())),
+        _ => res,
+    }
+}
+
+// State shared by the runs on every device
+struct RunState {
+    kernel_log: Option<KernelLog>,
+    taint_monitor: Option<TaintMonitor>,
+    failed: bool,
+    aborted: bool,
+}
+
+impl RunState {
+    // Results without the kernel log or taint checks that were asked for
+    // would look cleaner than they are, so we'd rather not run at all.
+    fn new(options: &RunOptions) -> Result<Self, RunError> {
+        let kernel_log = options
+            .kernel_log
+            .as_deref()
+            .map(|path| {
+                KernelLog::open(path).map_err(|source| RunError::KernelLog {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            })
+            .transpose()?;
+
+        let taint_monitor = options
+            .taint
+            .as_deref()
+            .map(|path| {
+                TaintMonitor::new(path).map_err(|source| RunError::Taint {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            kernel_log,
+            taint_monitor,
+            failed: false,
+            aborted: false,
+        })
+    }
+
+    // Whatever the kernel reported between two tests isn't related to either,
+    // but it still counts against the run, e.g. a fixture teardown tainting
+    // the kernel.
+    fn check_between_tests(&mut self, writer: &mut impl TestResultWriter, options: &RunOptions) {
+        match self.kernel_log.as_mut().map(KernelLog::read) {
+            Some(Ok(messages)) => {
+                if let Err(e) = check_kernel_log(Ok(()), &messages, options.kernel_log_policy) {
+                    if let TestError::DmesgFail(_) = e {
+                        self.failed = true;
+                    }
+
+                    writer.write_warning(&RunWarning::StrayKernelLog(e));
+                }
+            }
+            Some(Err(e)) => writer.write_warning(&RunWarning::KernelLog(e)),
+            None => {}
+        }
+
+        match self.taint_monitor.as_mut().map(TaintMonitor::check) {
+            Some(Ok(taint)) if !taint.is_empty() => {
+                writer.write_warning(&RunWarning::StrayTaint(taint));
+                self.failed = true;
 
-    let path = find_device(dev).unwrap();
+                if options.abort_on_taint {
+                    writer.write_warning(&RunWarning::AbortedOnTaint(String::from(
+                        "something outside of the tests",
+                    )));
+                    self.aborted = true;
+                }
+            }
+            Some(Err(e)) => writer.write_warning(&RunWarning::Taint(e)),
+            _ => {}
+        }
+    }
+}
+
+fn run_device(
+    writer: &mut impl TestResultWriter,
+    path: &Path,
+    suites: &[(String, Vec<Test>)],
+    options: &RunOptions,
+    state: &mut RunState,
+) {
+    for (test_module, tests) in suites {
+        writer.start_suite(test_module, tests);
 
-    for (test_module, tests) in get_test_suites() {
-        writer.start_suite(&test_module, &tests);
+        let suite_start = Instant::now();
+        let mut suite_fixtures = FixtureSet::default();
+        let mut stuck = false;
+        let mut abandoned = false;
 
         for test in tests {
-            writer.write_test(&test);
+            state.check_between_tests(writer, options);
 
-            let res = match test.test_fn {
-                TestFunction::NoArg(f) => f(),
-                TestFunction::WithFd(f) => {
-                    File::open(&path).map_err(|e| e.into()).and_then(|file| {
-                        let fd = file.as_fd();
+            if state.aborted {
+                break;
+            }
 
-                        if test.master {
-                            set_master(fd)?;
-                        }
+            writer.write_test(test);
 
-                        for cap in test.client_capabilities.into_iter().flatten() {
-                            set_client_capability(fd, cap)?;
-                        }
+            let device = TestDevice::open(path);
+            let timeout = test.timeout.or(options.timeout);
 
-                        f(file.as_fd())
+            let test_start = Instant::now();
+            let mut test_fixtures = FixtureSet::default();
+
+            // A stuck fixture setup keeps running, and might hold on to
+            // something the following tests of the suite need.
+            let res = if stuck {
+                Err(TestError::Skipped(String::from(
+                    "a fixture setup of the suite got stuck",
+                )))
+            } else {
+                check_device_requirements(test, &device)
+                    .and_then(|()| {
+                        setup_fixtures(
+                            test,
+                            &device,
+                            &mut suite_fixtures,
+                            &mut test_fixtures,
+                            timeout,
+                        )
+                    })
+                    .inspect_err(|e| {
+                        if let TestError::Timeout(_) = e {
+                            writer.write_warning(&RunWarning::FixtureStuck(test.full_name()));
+                            stuck = true;
+                        }
                     })
+                    .and_then(|fixtures| {
+                        run_test_on_device(test, &device, &fixtures, options.isolate, timeout)
+                    })
+            };
+
+            abandoned = !options.isolate && !stuck && matches!(res, Err(TestError::Timeout(_)));
+
+            // Whatever the outcome of the test, its fixtures need to go, unless
+            // it's still running and using them.
+            let res = if abandoned {
+                res
+            } else {
+                res.and(test_fixtures.teardown())
+            };
+
+            let duration = test_start.elapsed();
+
+            let res = match state.kernel_log.as_mut().map(KernelLog::read) {
+                Some(Ok(messages)) => {
+                    writer.write_kernel_log(test, &messages);
+                    check_kernel_log(res, &messages, options.kernel_log_policy)
                 }
-                TestFunction::WithPath(f) => f(&path),
+                Some(Err(e)) => {
+                    writer.write_warning(&RunWarning::KernelLog(e));
+                    res
+                }
+                None => res,
+            };
+
+            // Any result we got after the kernel got tainted is suspect.
+            let new_taint = match state.taint_monitor.as_mut().map(TaintMonitor::check) {
+                Some(Ok(taint)) if !taint.is_empty() => {
+                    writer.write_taint(test, taint);
+                    state.failed = true;
+                    true
+                }
+                Some(Err(e)) => {
+                    writer.write_warning(&RunWarning::Taint(e));
+                    false
+                }
+                _ => false,
             };
 
-            writer.write_result(&test, &res);
+            let verdict = options.expectations.verdict(test, &res);
+            writer.write_verdict(test, verdict, options.expectations.get(test));
+            writer.write_result(test, &res, duration);
 
-            result = result.and(res);
+            if verdict != Verdict::Expected {
+                state.failed = true;
+            }
+
+            if new_taint && options.abort_on_taint {
+                writer.write_warning(&RunWarning::AbortedOnTaint(test.full_name()));
+                state.aborted = true;
+                break;
+            }
+
+            if abandoned {
+                writer.write_warning(&RunWarning::TestAbandoned(test.full_name()));
+                state.aborted = true;
+                break;
+            }
+        }
+
+        if !abandoned {
+            if let Err(e) = suite_fixtures.teardown() {
+                writer.write_warning(&RunWarning::Fixture(e));
+            }
+        }
+
+        state.check_between_tests(writer, options);
+
+        writer.end_suite(suite_start.elapsed());
+
+        if state.aborted {
+            break;
         }
+    }
+}
+
+pub fn run_all(
+    writer: &mut impl TestResultWriter,
+    dev: DeviceSpecifier,
+    selection: &TestSelection,
+    options: &RunOptions,
+) -> RunResult {
+    let paths = match find_devices(&dev, options.all_devices) {
+        Ok(paths) => paths,
+        Err(e) => {
+            writer.write_device_error(&e);
+            return RunResult::DeviceError;
+        }
+    };
+
+    let mut state = match RunState::new(options) {
+        Ok(state) => state,
+        Err(e) => {
+            writer.write_run_error(&e);
+            return RunResult::Error;
+        }
+    };
+
+    let suites = get_test_suites(selection);
+
+    writer.start_run();
+
+    if let Some(taint) = state.taint_monitor.as_ref().map(TaintMonitor::taint) {
+        if !taint.is_empty() {
+            writer.write_warning(&RunWarning::AlreadyTainted(taint));
+        }
+    }
+
+    for path in paths {
+        let version = File::open(&path).and_then(|f| get_version(f.as_fd())).ok();
+
+        writer.start_device(&path, version.as_ref());
+        run_device(writer, &path, &suites, options, &mut state);
+        writer.end_device();
+
+        if state.aborted {
+            break;
+        }
+    }
+
+    writer.end_run();
+
+    // A failure on any of the devices fails the whole run.
+    if state.failed {
+        return RunResult::Failure;
+    }
+
+    RunResult::Success
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        fs::File,
+        io::{self, Write},
+        ops::Deref,
+        os::fd::{AsFd, AsRawFd},
+        path::{Path, PathBuf},
+        rc::Rc,
+        sync::atomic::{AtomicBool, AtomicI32, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use drm_helpers::DriverVersion;
+    use drm_uapi::DriverCapability;
+    use nix::{
+        fcntl::{flock, FlockArg},
+        unistd::{lseek, read, Whence},
+    };
+
+    use crate::{
+        check_kernel_log, check_requirements, group_by_suite, isolation::run_test_isolated,
+        list_tests, run_all, run_test_catch_unwind, run_test_on_device, run_test_with_timeout,
+        shuffle_tests, DeviceSpecifier, Expectation, ExpectedOutcome, Fixtures, KernelLogPolicy,
+        KernelMessage, Pattern, RunOptions, RunResult, RunState, RunWarning, TapResultWriter, Test,
+        TestDevice, TestError, TestFunction, TestResultWriter, TestSelection, Verdict,
+    };
+
+    #[derive(Clone, Default)]
+    pub(crate) struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Buffer {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // A path in the temporary directory, private to this process, and removed
+    // along with whatever got created there once dropped.
+    pub(crate) struct TempPath(PathBuf);
+
+    impl TempPath {
+        pub(crate) fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("cgt-{}-{name}", std::process::id())))
+        }
+    }
+
+    impl Deref for TempPath {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempPath {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = if self.0.is_dir() {
+                std::fs::remove_dir_all(&self.0)
+            } else {
+                std::fs::remove_file(&self.0)
+            };
+        }
+    }
+
+    // Feeds a writer with a run of four tests, passing, failing, skipped
+    // and timing out.
+    pub(crate) fn write_run(writer: &mut impl TestResultWriter) {
+        write_run_on(writer, &["/dev/dri/card0"]);
+    }
+
+    // Same as write_run, but against each of the devices.
+    pub(crate) fn write_run_on(writer: &mut impl TestResultWriter, devices: &[&str]) {
+        let tests = [
+            test("cgt::tests::dummy", "test_pass"),
+            test("cgt::tests::dummy", "test_fail"),
+            test("cgt::tests::dummy", "test_skip"),
+            test("cgt::tests::dummy", "test_timeout"),
+        ];
+        let results = [
+            (Ok(()), Duration::from_millis(250)),
+            (
+                Err(TestError::NotEqual(String::from("1"), String::from("2"))),
+                Duration::from_millis(20),
+            ),
+            (
+                Err(TestError::Skipped(String::from("requires <atomic>"))),
+                Duration::ZERO,
+            ),
+            (
+                Err(TestError::Timeout(Duration::from_secs(1))),
+                Duration::from_secs(1),
+            ),
+        ];
+
+        writer.start_run();
+        for device in devices {
+            writer.start_device(
+                Path::new(device),
+                Some(&DriverVersion {
+                    major: 1,
+                    minor: 0,
+                    patch: 0,
+                    name: String::from("vkms"),
+                    date: String::from("20180514"),
+                    desc: String::from("Virtual Kernel Mode Setting"),
+                }),
+            );
+            writer.start_suite("cgt::tests::dummy", &tests);
+            for (test, (res, duration)) in tests.iter().zip(results.iter()) {
+                writer.write_test(test);
+                writer.write_result(test, res, *duration);
+            }
+            writer.end_suite(results.iter().map(|(_, duration)| *duration).sum());
+            writer.end_device();
+        }
+        writer.end_run();
+    }
+
+    // Feeds a writer with a run of three tests, with the verdict of each: a
+    // known failure, a flaky test passing and a plain pass.
+    pub(crate) fn write_verdicts(writer: &mut impl TestResultWriter) {
+        let tests = [
+            test("cgt::tests::dummy", "test_known"),
+            test("cgt::tests::dummy", "test_flaky"),
+            test("cgt::tests::dummy", "test_pass"),
+        ];
+        let results = [
+            (
+                Err(TestError::NotEqual(String::from("1"), String::from("2"))),
+                Some(Expectation {
+                    outcome: ExpectedOutcome::Fail,
+                    comment: Some(String::from("https://bugs.example/42")),
+                }),
+            ),
+            (
+                Ok(()),
+                Some(Expectation {
+                    outcome: ExpectedOutcome::Flake,
+                    comment: None,
+                }),
+            ),
+            (Ok(()), None),
+        ];
+
+        writer.start_run();
+        writer.start_device(Path::new("/dev/dri/card0"), None);
+        writer.start_suite("cgt::tests::dummy", &tests);
+        for (test, (res, expected)) in tests.iter().zip(results.iter()) {
+            writer.write_test(test);
+            writer.write_verdict(test, Verdict::Expected, expected.as_ref());
+            writer.write_result(test, res, Duration::ZERO);
+        }
+        writer.end_suite(Duration::ZERO);
+        writer.end_device();
+        writer.end_run();
+    }
+
+    fn dummy() -> Result<(), TestError> {
+        Ok(())
+    }
+
+    pub(crate) const fn test(module_name: &'static str, test_name: &'static str) -> Test {
+        Test {
+            module_name,
+            test_name,
+            test_fn: TestFunction::NoArg(dummy),
+            master: false,
+            client_capabilities: [None; 8],
+            required_capabilities: [None; 8],
+            driver: None,
+            min_version: None,
+            timeout: None,
+            fixtures: [None; 8],
+        }
+    }
+
+    fn pattern(p: &str) -> Pattern {
+        Pattern::new(p).unwrap()
+    }
+
+    #[test]
+    fn selection_empty_matches_everything() {
+        let selection = TestSelection::new();
+
+        assert!(selection.matches(&test("cgt::tests::planes", "test_formats")));
+        assert!(selection.matches(&test("cgt::tests::dummy", "test_dummy")));
+    }
+
+    #[test]
+    fn selection_include() {
+        let selection = TestSelection::new().include(pattern("cgt::tests::planes::*"));
+
+        assert!(selection.matches(&test("cgt::tests::planes", "test_formats")));
+        assert!(!selection.matches(&test("cgt::tests::dummy", "test_dummy")));
+    }
+
+    #[test]
+    fn selection_exclude() {
+        let selection = TestSelection::new().exclude(pattern("*::test_dummy"));
 
-        writer.end_suite();
+        assert!(selection.matches(&test("cgt::tests::planes", "test_formats")));
+        assert!(!selection.matches(&test("cgt::tests::dummy", "test_dummy")));
     }
 
-    result.into()
+    #[test]
+    fn selection_exclude_wins_over_include() {
+        let selection = TestSelection::new()
+            .include(pattern("cgt::tests::*"))
+            .exclude(pattern("*dummy*"));
+
+        assert!(selection.matches(&test("cgt::tests::planes", "test_formats")));
+        assert!(!selection.matches(&test("cgt::tests::dummy", "test_dummy")));
+    }
+
+    #[test]
+    fn selection_name() {
+        let selection = TestSelection::new().name("cgt::tests::dummy::test_dummy");
+
+        assert!(selection.matches(&test("cgt::tests::dummy", "test_dummy")));
+        assert!(!selection.matches(&test("cgt::tests::dummy", "test_dummy_2")));
+        assert!(!selection.matches(&test("cgt::tests::planes", "test_formats")));
+    }
+
+    #[test]
+    fn selection_name_and_include() {
+        let selection = TestSelection::new()
+            .name("cgt::tests::dummy::test_dummy")
+            .include(pattern("cgt::tests::planes::*"));
+
+        assert!(selection.matches(&test("cgt::tests::dummy", "test_dummy")));
+        assert!(selection.matches(&test("cgt::tests::planes", "test_formats")));
+        assert!(!selection.matches(&test("cgt::tests::crtcs", "test_gamma")));
+    }
+
+    inventory::submit!(test("cgt_core::tests::b", "test_b"));
+    inventory::submit!(test("cgt_core::tests::a", "test_b"));
+    inventory::submit!(test("cgt_core::tests::b", "test_a"));
+    inventory::submit!(test("cgt_core::tests::a", "test_a"));
+    inventory::submit!(test("cgt_core::tests::c", "test_a"));
+
+    static RAN_IN_PROCESS: AtomicBool = AtomicBool::new(false);
+
+    inventory::submit!(Test {
+        test_fn: TestFunction::NoArg(|| {
+            RAN_IN_PROCESS.store(true, Ordering::SeqCst);
+            Ok(())
+        }),
+        ..test("cgt_core::tests::d", "test_in_process")
+    });
+
+    fn names(tests: &[Test]) -> Vec<String> {
+        tests.iter().map(Test::full_name).collect()
+    }
+
+    #[test]
+    fn list_tests_sorted() {
+        let tests = list_tests(&TestSelection::new());
+
+        assert_eq!(
+            names(&tests),
+            vec![
+                "cgt_core::tests::a::test_a",
+                "cgt_core::tests::a::test_b",
+                "cgt_core::tests::b::test_a",
+                "cgt_core::tests::b::test_b",
+                "cgt_core::tests::c::test_a",
+                "cgt_core::tests::d::test_in_process",
+                "cgt_core::tests::e::test_read",
+                "cgt_core::tests::e::test_unread",
+            ]
+        );
+    }
+
+    #[test]
+    fn list_tests_shuffled_is_reproducible() {
+        let selection = TestSelection::new().shuffle(42);
+
+        assert_eq!(
+            names(&list_tests(&selection)),
+            names(&list_tests(&selection))
+        );
+    }
+
+    #[test]
+    fn shuffle_keeps_suites_together() {
+        let tests = list_tests(&TestSelection::new());
+
+        for seed in 0..32 {
+            let shuffled = shuffle_tests(tests.clone(), seed);
+            let mut sorted = names(&shuffled);
+            sorted.sort();
+
+            assert_eq!(sorted, names(&tests));
+            assert_eq!(group_by_suite(shuffled).len(), 5);
+        }
+
+        assert!((0..32).any(|seed| names(&shuffle_tests(tests.clone(), seed)) != names(&tests)));
+    }
+
+    #[test]
+    fn timeout_not_reached() {
+        let test = test("cgt_core::tests", "test_timeout");
+
+        assert_eq!(
+            run_test_with_timeout(
+                &test,
+                &TestDevice::open(Path::new("/dev/null")),
+                &Fixtures::default(),
+                Some(Duration::from_secs(10))
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn timeout_reached() {
+        let mut test = test("cgt_core::tests", "test_timeout");
+        test.test_fn = TestFunction::NoArg(|| {
+            thread::sleep(Duration::from_secs(10));
+            Ok(())
+        });
+
+        assert_eq!(
+            run_test_with_timeout(
+                &test,
+                &TestDevice::open(Path::new("/dev/null")),
+                &Fixtures::default(),
+                Some(Duration::from_millis(100))
+            ),
+            Err(TestError::Timeout(Duration::from_millis(100)))
+        );
+    }
+
+    // Stands in for DRM master: only one open file can hold the lock, and it's
+    // only released once that file is closed.
+    fn lock(path: &Path) -> Result<File, TestError> {
+        let file = File::open(path)?;
+
+        flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock)?;
+
+        Ok(file)
+    }
+
+    #[test]
+    fn timeout_releases_resources() {
+        let path = TempPath::new("lock");
+        std::fs::write(&path, "").unwrap();
+
+        let mut stuck = test("cgt_core::tests", "test_stuck");
+        stuck.test_fn = TestFunction::WithPath(|path| {
+            let _file = lock(path)?;
+            thread::sleep(Duration::from_secs(10));
+            Ok(())
+        });
+
+        let mut next = test("cgt_core::tests", "test_next");
+        next.test_fn = TestFunction::WithPath(|path| lock(path).map(|_| ()));
+
+        let device = TestDevice::open(&path);
+        let timeout = Some(Duration::from_millis(200));
+
+        assert_eq!(
+            run_test_isolated(&stuck, &device, &Fixtures::default(), timeout),
+            Err(TestError::Timeout(Duration::from_millis(200)))
+        );
+        assert_eq!(
+            run_test_isolated(&next, &device, &Fixtures::default(), timeout),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn capability_query_error() {
+        let mut test = test("cgt_core::tests", "test_capability");
+        test.required_capabilities[0] = Some(DriverCapability::DumbBuffer);
+
+        // /dev/null doesn't know about DRM ioctls at all, which has nothing
+        // to do with the capability being unsupported.
+        let file = File::open("/dev/null").unwrap();
+
+        assert!(matches!(
+            check_requirements(file.as_fd(), &test),
+            Err(TestError::Io(_))
+        ));
+    }
+
+    static DEVICE_FD: AtomicI32 = AtomicI32::new(-1);
+
+    #[test]
+    fn device_file_shared() {
+        let mut test = test("cgt_core::tests", "test_device_file");
+        test.test_fn =
+            TestFunction::WithFd(
+                |fd| match DEVICE_FD.swap(fd.as_raw_fd(), Ordering::SeqCst) {
+                    -1 => Ok(()),
+                    prev if prev == fd.as_raw_fd() => Ok(()),
+                    prev => Err(TestError::NotEqual(
+                        prev.to_string(),
+                        fd.as_raw_fd().to_string(),
+                    )),
+                },
+            );
+
+        let device = TestDevice::open(Path::new("/dev/null"));
+
+        // Isolated tests get the very same file.
+        for isolate in [false, true] {
+            assert_eq!(
+                run_test_on_device(&test, &device, &Fixtures::default(), isolate, None),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn panic_caught() {
+        let mut test = test("cgt_core::tests", "test_panic");
+        test.test_fn = TestFunction::NoArg(|| panic!("oops"));
+
+        let device = TestDevice::open(Path::new("/dev/null"));
+        let res = run_test_catch_unwind(&test, &device, &Fixtures::default());
+
+        assert!(matches!(
+            res,
+            Err(TestError::Panicked(ref msg)) if msg.starts_with("oops at ") && msg.contains("lib.rs")
+        ));
+    }
+
+    #[test]
+    fn panic_caught_with_timeout() {
+        let mut test = test("cgt_core::tests", "test_panic");
+        test.test_fn = TestFunction::NoArg(|| panic!("oops"));
+
+        let res = run_test_with_timeout(
+            &test,
+            &TestDevice::open(Path::new("/dev/null")),
+            &Fixtures::default(),
+            Some(Duration::from_secs(10)),
+        );
+
+        assert!(matches!(
+            res,
+            Err(TestError::Panicked(ref msg)) if msg.starts_with("oops at ") && msg.contains("lib.rs")
+        ));
+    }
+
+    fn kernel_message(level: u8, message: &str) -> KernelMessage {
+        KernelMessage {
+            level,
+            sequence: 0,
+            timestamp: Duration::ZERO,
+            message: String::from(message),
+        }
+    }
+
+    #[test]
+    fn kernel_log_policy() {
+        let messages = [
+            kernel_message(6, "[drm] Initialized vkms"),
+            kernel_message(4, "WARNING: CPU: 0 PID: 42 at drm_atomic.c:42"),
+        ];
+
+        assert_eq!(
+            check_kernel_log(Ok(()), &messages, KernelLogPolicy::Ignore),
+            Ok(())
+        );
+        assert_eq!(
+            check_kernel_log(Ok(()), &messages, KernelLogPolicy::Warn),
+            Err(TestError::DmesgWarn(String::from(
+                "WARNING: CPU: 0 PID: 42 at drm_atomic.c:42"
+            )))
+        );
+        assert_eq!(
+            check_kernel_log(Ok(()), &messages, KernelLogPolicy::Fail),
+            Err(TestError::DmesgFail(String::from(
+                "WARNING: CPU: 0 PID: 42 at drm_atomic.c:42"
+            )))
+        );
+        assert_eq!(
+            check_kernel_log(Ok(()), &messages[..1], KernelLogPolicy::Fail),
+            Ok(())
+        );
+        assert_eq!(
+            TestError::DmesgFail(String::from("oops")).to_string(),
+            "Kernel log reported a failure: oops"
+        );
+
+        // The test error is more relevant than the kernel log
+        assert_eq!(
+            check_kernel_log(
+                Err(TestError::Unspecified),
+                &messages,
+                KernelLogPolicy::Fail
+            ),
+            Err(TestError::Unspecified)
+        );
+    }
+
+    // Reading from the file moves its offset, which a test sharing the same
+    // file would see.
+    inventory::submit!(Test {
+        test_fn: TestFunction::WithFd(|fd| {
+            read(fd.as_raw_fd(), &mut [0; 1])?;
+            Ok(())
+        }),
+        ..test("cgt_core::tests::e", "test_read")
+    });
+
+    inventory::submit!(Test {
+        test_fn: TestFunction::WithFd(|fd| {
+            match lseek(fd.as_raw_fd(), 0, Whence::SeekCur)? {
+                0 => Ok(()),
+                offset => Err(TestError::NotEqual(offset.to_string(), String::from("0"))),
+            }
+        }),
+        ..test("cgt_core::tests::e", "test_unread")
+    });
+
+    #[test]
+    fn device_file_per_test() {
+        let path = TempPath::new("device");
+        std::fs::write(&path, "data").unwrap();
+
+        let res = run_all(
+            &mut TapResultWriter::with_output(Box::new(Buffer::default())),
+            DeviceSpecifier::Path(path.to_path_buf()),
+            &TestSelection::new()
+                .name("cgt_core::tests::e::test_read")
+                .name("cgt_core::tests::e::test_unread"),
+            &RunOptions::default(),
+        );
+
+        assert!(matches!(res, RunResult::Success));
+    }
+
+    #[test]
+    fn default_run_in_process() {
+        let mut writer = TapResultWriter::with_output(Box::new(Buffer::default()));
+
+        let res = run_all(
+            &mut writer,
+            DeviceSpecifier::Path(PathBuf::from("/dev/null")),
+            &TestSelection::new().name("cgt_core::tests::d::test_in_process"),
+            &RunOptions::default(),
+        );
+
+        // A forked test would have set the flag in the child only.
+        assert!(matches!(res, RunResult::Success));
+        assert!(RAN_IN_PROCESS.load(Ordering::SeqCst));
+    }
+
+    #[derive(Default)]
+    struct WarningRecorder(Vec<String>);
+
+    impl TestResultWriter for WarningRecorder {
+        fn new() -> Self {
+            Self::default()
+        }
+
+        fn write_test(&mut self, _test: &Test) {}
+
+        fn write_result(
+            &mut self,
+            _test: &Test,
+            _res: &Result<(), TestError>,
+            _duration: Duration,
+        ) {
+        }
+
+        fn write_warning(&mut self, warning: &RunWarning) {
+            self.0.push(warning.to_string());
+        }
+    }
+
+    #[test]
+    fn issues_between_tests() {
+        let kmsg = TempPath::new("between-kmsg");
+        std::fs::write(&kmsg, "").unwrap();
+        let taint = TempPath::new("between-taint");
+        std::fs::write(&taint, "0\n").unwrap();
+
+        let options = RunOptions {
+            kernel_log: Some(kmsg.to_path_buf()),
+            kernel_log_policy: KernelLogPolicy::Fail,
+            taint: Some(taint.to_path_buf()),
+            abort_on_taint: true,
+            ..RunOptions::default()
+        };
+        let mut state = RunState::new(&options).unwrap();
+        let mut writer = WarningRecorder::default();
+
+        state.check_between_tests(&mut writer, &options);
+        assert!(writer.0.is_empty());
+        assert!(!state.failed);
+
+        // Say, a suite fixture teardown going wrong
+        std::fs::write(&kmsg, "4,1,100,-;WARNING: CPU: 0 PID: 42\n").unwrap();
+        std::fs::write(&taint, "512\n").unwrap();
+
+        state.check_between_tests(&mut writer, &options);
+        assert_eq!(
+            writer.0,
+            vec![
+                "Kernel log reported a failure: WARNING: CPU: 0 PID: 42, outside of any test",
+                "Kernel tainted outside of any test: W (kernel issued warning)",
+                "Kernel tainted by something outside of the tests, aborting the run",
+            ]
+        );
+        assert!(state.failed);
+        assert!(state.aborted);
+    }
+
+    #[test]
+    fn kernel_log_unavailable() {
+        let buffer = Buffer::default();
+        let mut writer = TapResultWriter::with_output(Box::new(buffer.clone()));
+        let options = RunOptions {
+            kernel_log: Some(PathBuf::from("/nonexistent/kmsg")),
+            ..RunOptions::default()
+        };
+
+        let res = run_all(
+            &mut writer,
+            DeviceSpecifier::Path(PathBuf::from("/dev/null")),
+            &TestSelection::new().name("none"),
+            &options,
+        );
+
+        // No test ran, so the run must not look successful.
+        assert!(matches!(res, RunResult::Error));
+        assert!(buffer.contents().starts_with(
+            "TAP version 14\nBail out! Couldn't open the kernel log /nonexistent/kmsg:"
+        ));
+    }
 }
@@ -0,0 +1,180 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_TAINT_PATH: &str = "/proc/sys/kernel/tainted";
+
+// See Documentation/admin-guide/tainted-kernels.rst
+const TAINT_FLAGS: [(char, &str); 19] = [
+    ('P', "proprietary module was loaded"),
+    ('F', "module was force loaded"),
+    ('S', "kernel running on an out of specification system"),
+    ('R', "module was force unloaded"),
+    ('M', "processor reported a Machine Check Exception"),
+    ('B', "bad page referenced or some unexpected page flags"),
+    ('U', "taint requested by userspace application"),
+    ('D', "kernel died recently, i.e. there was an OOPS or BUG"),
+    ('A', "ACPI table overridden by user"),
+    ('W', "kernel issued warning"),
+    ('C', "staging driver was loaded"),
+    ('I', "workaround for bug in platform firmware applied"),
+    ('O', "externally-built (\"out-of-tree\") module was loaded"),
+    ('E', "unsigned module was loaded"),
+    ('L', "soft lockup occurred"),
+    ('K', "kernel has been live patched"),
+    ('X', "auxiliary taint, defined for and used by distros"),
+    ('T', "kernel was built with the struct randomization plugin"),
+    ('N', "an in-kernel test has been run"),
+];
+
+/// A set of kernel taint flags
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Taint(pub u64);
+
+impl Taint {
+    #[must_use]
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the letter and description of every flag set. Unknown flags
+    /// are reported with a `?` and their bit number.
+    #[must_use]
+    pub fn flags(self) -> Vec<(char, String)> {
+        (0..u64::BITS)
+            .filter(|bit| self.0 & (1 << bit) != 0)
+            .map(|bit| match TAINT_FLAGS.get(bit as usize) {
+                Some((letter, desc)) => (*letter, (*desc).to_string()),
+                None => ('?', format!("unknown taint bit {bit}")),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Taint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags: Vec<String> = self
+            .flags()
+            .into_iter()
+            .map(|(letter, desc)| format!("{letter} ({desc})"))
+            .collect();
+
+        write!(f, "{}", flags.join(", "))
+    }
+}
+
+/// Watches the kernel taint mask for new flags
+#[derive(Debug)]
+pub struct TaintMonitor {
+    path: PathBuf,
+    last: Taint,
+}
+
+fn read_taint(path: &Path) -> io::Result<Taint> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map(Taint)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl TaintMonitor {
+    /// Creates a monitor for the taint mask found at `path`, usually
+    /// [`DEFAULT_TAINT_PATH`].
+    ///
+    /// # Errors
+    ///
+    /// Will return [`std::io::Error`] if the taint mask can't be read.
+    pub fn new(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            last: read_taint(path)?,
+        })
+    }
+
+    /// Returns the taint mask as it was when last read.
+    #[must_use]
+    pub fn taint(&self) -> Taint {
+        self.last
+    }
+
+    /// Reads the taint mask again, and returns the flags that weren't set
+    /// the last time.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`std::io::Error`] if the taint mask can't be read.
+    pub fn check(&mut self) -> io::Result<Taint> {
+        let current = read_taint(&self.path)?;
+        let new = Taint(current.0 & !self.last.0);
+
+        self.last = current;
+
+        Ok(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TempPath;
+
+    use super::{Taint, TaintMonitor};
+
+    fn taint_file(name: &str, content: &str) -> TempPath {
+        let path = TempPath::new(&format!("taint-{name}"));
+
+        std::fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(Taint(0).flags(), Vec::new());
+        assert_eq!(
+            Taint(1 << 9 | 1 << 12).flags(),
+            vec![
+                ('W', String::from("kernel issued warning")),
+                (
+                    'O',
+                    String::from("externally-built (\"out-of-tree\") module was loaded")
+                ),
+            ]
+        );
+        assert_eq!(
+            Taint(1 << 40).flags(),
+            vec![('?', String::from("unknown taint bit 40"))]
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Taint(1 << 7 | 1 << 9).to_string(),
+            "D (kernel died recently, i.e. there was an OOPS or BUG), W (kernel issued warning)"
+        );
+    }
+
+    #[test]
+    fn new_flags() {
+        let path = taint_file("new", "4096\n");
+        let mut monitor = TaintMonitor::new(&path).unwrap();
+
+        assert_eq!(monitor.taint(), Taint(1 << 12));
+        assert!(monitor.check().unwrap().is_empty());
+
+        std::fs::write(&path, "4608\n").unwrap();
+        assert_eq!(monitor.check().unwrap(), Taint(1 << 9));
+
+        // Flags only get reported once
+        assert!(monitor.check().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        let path = taint_file("invalid", "garbage\n");
+
+        assert!(TaintMonitor::new(&path).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use drm_helpers::DriverVersion;
+
+use crate::{
+    DeviceError, Expectation, KernelMessage, RunError, Taint, Test, TestError, TestOutcome,
+    TestResultWriter, Verdict,
+};
+
+// TAP descriptions and directives end at the end of the line, and a '#'
+// would start a directive.
+fn description(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('#', "\\#")
+        .replace(['\n', '\r'], " ")
+}
+
+/// Writes the results following the TAP version 14 specification, with one
+/// subtest per test module.
+///
+/// Every line is written as soon as it's known, so the output of an
+/// interrupted run is still usable.
+pub struct TapResultWriter {
+    output: Box<dyn Write>,
+    started: bool,
+    num_suites: usize,
+    suite_name: String,
+    suite_failed: bool,
+    num_tests: usize,
+    kernel_log: Vec<String>,
+    taint: Option<Taint>,
+    verdict: Option<(Verdict, Option<Expectation>)>,
+}
+
+impl TapResultWriter {
+    #[must_use]
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self {
+            output,
+            started: false,
+            num_suites: 0,
+            suite_name: String::new(),
+            suite_failed: false,
+            num_tests: 0,
+            kernel_log: Vec::new(),
+            taint: None,
+            verdict: None,
+        }
+    }
+
+    fn start(&mut self) {
+        if !self.started {
+            let _ = writeln!(self.output, "TAP version 14");
+            self.started = true;
+        }
+    }
+}
+
+impl TestResultWriter for TapResultWriter {
+    fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    fn write_device_error(&mut self, err: &DeviceError) {
+        self.start();
+
+        let _ = writeln!(self.output, "Bail out! {}", description(&err.to_string()));
+        let _ = self.output.flush();
+    }
+
+    fn write_run_error(&mut self, err: &RunError) {
+        self.start();
+
+        let _ = writeln!(self.output, "Bail out! {}", description(&err.to_string()));
+        let _ = self.output.flush();
+    }
+
+    fn start_run(&mut self) {
+        self.start();
+    }
+
+    fn start_device(&mut self, device: &Path, version: Option<&DriverVersion>) {
+        let _ = writeln!(self.output, "# device: {}", device.display());
+
+        if let Some(version) = version {
+            let _ = writeln!(
+                self.output,
+                "# driver: {} {}.{}.{} ({}, {})",
+                version.name,
+                version.major,
+                version.minor,
+                version.patch,
+                version.date,
+                version.desc
+            );
+        }
+
+        let _ = self.output.flush();
+    }
+
+    fn start_suite(&mut self, name: &str, tests: &[Test]) {
+        self.start();
+
+        self.suite_name = description(name);
+        self.suite_failed = false;
+        self.num_tests = 0;
+
+        let _ = writeln!(self.output, "# Subtest: {}", self.suite_name);
+        let _ = writeln!(self.output, "    1..{}", tests.len());
+        let _ = self.output.flush();
+    }
+
+    fn write_test(&mut self, _test: &Test) {}
+
+    fn write_result(&mut self, test: &Test, res: &Result<(), TestError>, duration: Duration) {
+        let outcome = TestOutcome::from(res);
+        let name = description(test.test_name);
+        let verdict = self.verdict.take();
+
+        self.num_tests += 1;
+
+        if let Some((Verdict::UnexpectedPass, _)) = verdict {
+            self.suite_failed = true;
+        }
+
+        let _ = match (res, &verdict) {
+            (Ok(()) | Err(TestError::DmesgWarn(_)), _) => {
+                writeln!(self.output, "    ok {} - {name}", self.num_tests)
+            }
+            (Err(TestError::Skipped(reason)), _) => writeln!(
+                self.output,
+                "    ok {} - {name} # SKIP {}",
+                self.num_tests,
+                description(reason)
+            ),
+            // Known failures don't fail the suite.
+            (Err(_), Some((Verdict::Expected, Some(expected)))) => writeln!(
+                self.output,
+                "    not ok {} - {name} # TODO {}",
+                self.num_tests,
+                description(expected.comment.as_deref().unwrap_or("known failure"))
+            ),
+            (Err(_), _) => {
+                self.suite_failed = true;
+                writeln!(self.output, "    not ok {} - {name}", self.num_tests)
+            }
+        };
+
+        let _ = writeln!(self.output, "      ---");
+        let _ = writeln!(self.output, "      outcome: {}", outcome.as_str());
+        let _ = writeln!(
+            self.output,
+            "      duration_ms: {:.3}",
+            duration.as_secs_f64() * 1000.0
+        );
+
+        if let Err(e) = res {
+            // A JSON string is also a valid YAML double-quoted scalar.
+            let _ = writeln!(
+                self.output,
+                "      message: {}",
+                serde_json::Value::from(e.to_string())
+            );
+        }
+
+        if !self.kernel_log.is_empty() {
+            let _ = writeln!(self.output, "      dmesg:");
+
+            for line in self.kernel_log.drain(..) {
+                let _ = writeln!(self.output, "        - {}", serde_json::Value::from(line));
+            }
+        }
+
+        if let Some(taint) = self.taint.take() {
+            let _ = writeln!(
+                self.output,
+                "      taint: {}",
+                serde_json::Value::from(taint.to_string())
+            );
+        }
+
+        if let Some((verdict, expected)) = verdict {
+            let _ = writeln!(self.output, "      verdict: {}", verdict.as_str());
+
+            if let Some(comment) = expected.and_then(|e| e.comment) {
+                let _ = writeln!(
+                    self.output,
+                    "      expectation: {}",
+                    serde_json::Value::from(comment)
+                );
+            }
+        }
+
+        let _ = writeln!(self.output, "      ...");
+        let _ = self.output.flush();
+    }
+
+    fn write_kernel_log(&mut self, _test: &Test, messages: &[KernelMessage]) {
+        self.kernel_log = messages.iter().map(ToString::to_string).collect();
+    }
+
+    fn write_taint(&mut self, _test: &Test, taint: Taint) {
+        self.taint = Some(taint);
+    }
+
+    // A plain pass or failure needs no more explanation than its "ok" line.
+    fn write_verdict(&mut self, _test: &Test, verdict: Verdict, expected: Option<&Expectation>) {
+        if expected.is_some() || verdict != Verdict::Expected {
+            self.verdict = Some((verdict, expected.cloned()));
+        }
+    }
+
+    fn end_suite(&mut self, _duration: Duration) {
+        self.num_suites += 1;
+
+        let _ = writeln!(
+            self.output,
+            "{} {} - {}",
+            if self.suite_failed { "not ok" } else { "ok" },
+            self.num_suites,
+            self.suite_name
+        );
+        let _ = self.output.flush();
+    }
+
+    fn end_run(&mut self) {
+        self.start();
+
+        let _ = writeln!(self.output, "1..{}", self.num_suites);
+        let _ = self.output.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{write_run, write_run_on, write_verdicts, Buffer},
+        DeviceError, TestResultWriter,
+    };
+
+    use super::{description, TapResultWriter};
+
+    #[test]
+    fn description_escape() {
+        assert_eq!(description("a # b\\c\nd"), "a \\# b\\\\c d");
+    }
+
+    #[test]
+    fn report() {
+        let buffer = Buffer::default();
+        let mut writer = TapResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run(&mut writer);
+
+        let report = buffer.contents();
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(lines[0], "TAP version 14");
+        assert_eq!(lines[1], "# device: /dev/dri/card0");
+        assert_eq!(lines[3], "# Subtest: cgt::tests::dummy");
+        assert_eq!(lines[4], "    1..4");
+        assert_eq!(lines[5], "    ok 1 - test_pass");
+        assert_eq!(lines[8], "      duration_ms: 250.000");
+        assert!(report.contains("    not ok 2 - test_fail\n      ---\n      outcome: fail\n"));
+        assert!(report.contains("      message: \"Values 1 and 2 are not equal\"\n"));
+        assert!(report.contains("    ok 3 - test_skip # SKIP requires <atomic>\n"));
+        assert!(report.contains("    not ok 4 - test_timeout\n      ---\n      outcome: timeout\n"));
+        assert!(report.ends_with("not ok 1 - cgt::tests::dummy\n1..1\n"));
+    }
+
+    #[test]
+    fn verdicts() {
+        let buffer = Buffer::default();
+        let mut writer = TapResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_verdicts(&mut writer);
+
+        let report = buffer.contents();
+
+        assert!(report.contains("    not ok 1 - test_known # TODO https://bugs.example/42\n"));
+        assert!(report
+            .contains("      verdict: expected\n      expectation: \"https://bugs.example/42\"\n"));
+        assert!(report.contains("    ok 2 - test_flaky\n"));
+        // A plain pass gets no verdict.
+        assert_eq!(report.matches("verdict:").count(), 2);
+
+        // The known failure doesn't fail the suite.
+        assert!(report.ends_with("ok 1 - cgt::tests::dummy\n1..1\n"));
+        assert!(!report.contains("not ok 1 - cgt::tests::dummy"));
+    }
+
+    #[test]
+    fn several_devices() {
+        let buffer = Buffer::default();
+        let mut writer = TapResultWriter::with_output(Box::new(buffer.clone()));
+
+        write_run_on(&mut writer, &["/dev/dri/card0", "/dev/dri/card1"]);
+
+        let report = buffer.contents();
+
+        assert!(report.starts_with("TAP version 14\n# device: /dev/dri/card0\n"));
+        assert!(report.contains("not ok 1 - cgt::tests::dummy\n# device: /dev/dri/card1\n"));
+        assert!(report.ends_with("not ok 2 - cgt::tests::dummy\n1..2\n"));
+    }
+
+    #[test]
+    fn bail_out() {
+        let buffer = Buffer::default();
+        let mut writer = TapResultWriter::with_output(Box::new(buffer.clone()));
+
+        writer.write_device_error(&DeviceError::NoMatchingDriver {
+            driver: String::from("vkms"),
+            candidates: Vec::new(),
+        });
+
+        assert_eq!(
+            buffer.contents(),
+            "TAP version 14\nBail out! No device using the vkms driver: no DRM device found\n"
+        );
+    }
+}
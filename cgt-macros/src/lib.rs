@@ -1,8 +1,11 @@
 use attribute_derive::FromAttr;
 use proc_macro::TokenStream;
-use proc_macro_error::{emit_error, proc_macro_error};
-use quote::{quote, ToTokens};
-use syn::{parse::Parse, parse_macro_input, Expr, Ident, ItemFn, Token};
+use proc_macro_error::proc_macro_error;
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse::Parse, parse_macro_input, spanned::Spanned, Expr, FnArg, GenericArgument, Ident, ItemFn,
+    LitStr, Pat, PathArguments, ReturnType, Token, Type,
+};
 
 #[proc_macro]
 pub fn cgt_assert(item: TokenStream) -> TokenStream {
@@ -40,6 +43,18 @@ pub fn cgt_assert_ok(item: TokenStream) -> TokenStream {
     .into()
 }
 
+#[proc_macro]
+pub fn cgt_require(item: TokenStream) -> TokenStream {
+    let input: proc_macro2::TokenStream = item.into();
+
+    quote! {
+        if !(#input) {
+            return Err(TestError::Skipped(stringify!(#input).to_string()));
+        }
+    }
+    .into()
+}
+
 #[allow(dead_code)]
 struct AssertionInput {
     left: Expr,
@@ -71,19 +86,141 @@ pub fn cgt_assert_eq(input: TokenStream) -> TokenStream {
     .into()
 }
 
+#[derive(Debug, FromAttr)]
+struct BasicTestAttributes {
+    timeout: Option<u64>,
+}
+
+fn timeout_tokens(timeout: Option<u64>) -> ExplicitOption<proc_macro2::TokenStream> {
+    timeout
+        .map(|secs| quote! { ::std::time::Duration::from_secs(#secs) })
+        .into()
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TestKind {
+    NoArg,
+    WithPath,
+    WithFd,
+}
+
+impl TestKind {
+    fn num_args(self) -> usize {
+        match self {
+            TestKind::NoArg => 0,
+            TestKind::WithPath | TestKind::WithFd => 1,
+        }
+    }
+}
+
+// The arguments following the ones the runner passes to the test are
+// fixtures, taken by reference and named after the fixture they use.
+fn fixture_args(input: &ItemFn, kind: TestKind) -> syn::Result<Vec<(Ident, Type)>> {
+    let mut fixtures = Vec::new();
+
+    for arg in input.sig.inputs.iter().skip(kind.num_args()) {
+        let FnArg::Typed(arg) = arg else {
+            return Err(syn::Error::new(arg.span(), "Tests can't take self"));
+        };
+
+        let Pat::Ident(ref name) = *arg.pat else {
+            return Err(syn::Error::new(
+                arg.pat.span(),
+                "Expected the name of a fixture",
+            ));
+        };
+
+        let Type::Reference(ref ty) = *arg.ty else {
+            return Err(syn::Error::new(
+                arg.ty.span(),
+                "Fixtures must be taken by reference",
+            ));
+        };
+
+        fixtures.push((name.ident.clone(), (*ty.elem).clone()));
+    }
+
+    if fixtures.len() > 8 {
+        return Err(syn::Error::new(
+            input.sig.inputs.span(),
+            "Tests can use at most 8 fixtures",
+        ));
+    }
+
+    Ok(fixtures)
+}
+
+// Returns the test function to register, and the fixtures it uses. Tests
+// using fixtures are wrapped in a function retrieving them first.
+fn test_function(
+    input: &ItemFn,
+    kind: TestKind,
+) -> syn::Result<(proc_macro2::TokenStream, [ExplicitOption<String>; 8])> {
+    let fn_ident = &input.sig.ident;
+    let fixtures = fixture_args(input, kind)?;
+
+    let mut names: [ExplicitOption<String>; 8] = Default::default();
+    for (name, (ident, _)) in names.iter_mut().zip(&fixtures) {
+        *name = ExplicitOption::Some(ident.to_string());
+    }
+
+    let variant = match kind {
+        TestKind::NoArg => quote! { NoArg },
+        TestKind::WithPath => quote! { WithPath },
+        TestKind::WithFd => quote! { WithFd },
+    };
+
+    if fixtures.is_empty() {
+        return Ok((
+            quote! { cgt_core::TestFunction::#variant(#fn_ident) },
+            names,
+        ));
+    }
+
+    let (params, args) = match kind {
+        TestKind::NoArg => (quote! {}, quote! {}),
+        TestKind::WithPath => (quote! { path: &::std::path::Path }, quote! { path, }),
+        TestKind::WithFd => (quote! { fd: ::std::os::fd::BorrowedFd<'_> }, quote! { fd, }),
+    };
+
+    let wrapper = format_ident!("__cgt_fixtures_{}", fn_ident);
+    let idents = fixtures.iter().map(|(ident, _)| ident);
+    let getters = fixtures.iter().map(|(ident, ty)| {
+        let name = ident.to_string();
+
+        quote! { let #ident = cgt_core::fixture::<#ty>(#name)?; }
+    });
+
+    Ok((
+        quote! {
+            {
+                fn #wrapper(#params) -> Result<(), cgt_core::TestError> {
+                    #(#getters)*
+                    #fn_ident(#args #(&#idents),*)
+                }
+
+                cgt_core::TestFunction::#variant(#wrapper)
+            }
+        },
+        names,
+    ))
+}
+
 #[proc_macro_error]
 #[proc_macro_attribute]
 pub fn cgt_test(args: TokenStream, item: TokenStream) -> TokenStream {
-    if !args.is_empty() {
-        let args: proc_macro2::TokenStream = args.into();
-
-        emit_error! { args, "This macro doesn't use any attribute" };
-    }
+    let attrs: BasicTestAttributes = parse_macro_input!(args);
+    let timeout = timeout_tokens(attrs.timeout);
 
     let input = parse_macro_input!(item as ItemFn);
     let fn_ident = &input.sig.ident;
     let fn_name = fn_ident.to_string();
 
+    let (test_fn, fixtures) = match test_function(&input, TestKind::NoArg) {
+        Ok(res) => res,
+        Err(e) => return e.into_compile_error().into(),
+    };
+
     quote! {
         #input
 
@@ -91,9 +228,14 @@ pub fn cgt_test(args: TokenStream, item: TokenStream) -> TokenStream {
             cgt_core::Test {
                 module_name: module_path!(),
                 test_name: #fn_name,
-                test_fn: cgt_core::TestFunction::NoArg(#fn_ident),
+                test_fn: #test_fn,
                 master: false,
                 client_capabilities: [None; 8],
+                required_capabilities: [None; 8],
+                driver: None,
+                min_version: None,
+                timeout: #timeout,
+                fixtures: [#(#fixtures),*],
             }
         );
     }
@@ -103,16 +245,18 @@ pub fn cgt_test(args: TokenStream, item: TokenStream) -> TokenStream {
 #[proc_macro_error]
 #[proc_macro_attribute]
 pub fn cgt_test_with_path(args: TokenStream, item: TokenStream) -> TokenStream {
-    if !args.is_empty() {
-        let args: proc_macro2::TokenStream = args.into();
-
-        emit_error! { args, "This macro doesn't use any attribute" };
-    }
+    let attrs: BasicTestAttributes = parse_macro_input!(args);
+    let timeout = timeout_tokens(attrs.timeout);
 
     let input = parse_macro_input!(item as ItemFn);
     let fn_ident = &input.sig.ident;
     let fn_name = fn_ident.to_string();
 
+    let (test_fn, fixtures) = match test_function(&input, TestKind::WithPath) {
+        Ok(res) => res,
+        Err(e) => return e.into_compile_error().into(),
+    };
+
     quote! {
         #input
 
@@ -120,9 +264,14 @@ pub fn cgt_test_with_path(args: TokenStream, item: TokenStream) -> TokenStream {
             cgt_core::Test {
                 module_name: module_path!(),
                 test_name: #fn_name,
-                test_fn: cgt_core::TestFunction::WithPath(#fn_ident),
+                test_fn: #test_fn,
                 master: false,
                 client_capabilities: [None; 8],
+                required_capabilities: [None; 8],
+                driver: None,
+                min_version: None,
+                timeout: #timeout,
+                fixtures: [#(#fixtures),*],
             }
         );
     }
@@ -135,11 +284,36 @@ struct TestAttributes {
 
     #[attribute(optional)]
     capabilities: Vec<Ident>,
+
+    #[attribute(optional)]
+    requires_caps: Vec<Ident>,
+
+    driver: Option<String>,
+
+    min_version: Option<LitStr>,
+
+    timeout: Option<u64>,
 }
 
-#[derive(Clone, Copy, Debug)]
+fn parse_version(lit: &LitStr) -> Option<(i32, i32, i32)> {
+    let value = lit.value();
+    let mut parts = value.split('.').map(str::parse::<i32>);
+
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((major, minor, patch))
+}
+
+#[derive(Clone, Copy, Debug, Default)]
 enum ExplicitOption<T> {
     Some(T),
+    #[default]
     None,
 }
 
@@ -180,10 +354,43 @@ pub fn cgt_test_with_fd(args: TokenStream, item: TokenStream) -> TokenStream {
         *caps = attrs.capabilities.get(idx).into();
     }
 
+    if let Some(cap) = attrs.requires_caps.get(8) {
+        return syn::Error::new(cap.span(), "At most 8 required capabilities")
+            .into_compile_error()
+            .into();
+    }
+
+    let mut required_caps: [ExplicitOption<&Ident>; 8] = [ExplicitOption::None; 8];
+    for (idx, caps) in required_caps.iter_mut().enumerate() {
+        *caps = attrs.requires_caps.get(idx).into();
+    }
+
+    let driver: ExplicitOption<String> = attrs.driver.into();
+    let timeout = timeout_tokens(attrs.timeout);
+
+    let min_version: ExplicitOption<proc_macro2::TokenStream> = match attrs.min_version {
+        Some(ref lit) => match parse_version(lit) {
+            Some((major, minor, patch)) => {
+                ExplicitOption::Some(quote! { (#major, #minor, #patch) })
+            }
+            None => {
+                return syn::Error::new(lit.span(), "Expected a version such as \"1.0\"")
+                    .into_compile_error()
+                    .into();
+            }
+        },
+        None => ExplicitOption::None,
+    };
+
     let input = parse_macro_input!(item as ItemFn);
     let fn_ident = &input.sig.ident;
     let fn_name = fn_ident.to_string();
 
+    let (test_fn, fixtures) = match test_function(&input, TestKind::WithFd) {
+        Ok(res) => res,
+        Err(e) => return e.into_compile_error().into(),
+    };
+
     quote! {
         #input
 
@@ -191,9 +398,128 @@ pub fn cgt_test_with_fd(args: TokenStream, item: TokenStream) -> TokenStream {
             cgt_core::Test {
                 module_name: module_path!(),
                 test_name: #fn_name,
-                test_fn: cgt_core::TestFunction::WithFd(#fn_ident),
+                test_fn: #test_fn,
                 master: #master,
-                client_capabilities: [#(#caps),*]
+                client_capabilities: [#(#caps),*],
+                required_capabilities: [#(#required_caps),*],
+                driver: #driver,
+                min_version: #min_version,
+                timeout: #timeout,
+                fixtures: [#(#fixtures),*],
+            }
+        );
+    }
+    .into()
+}
+
+#[derive(Debug, FromAttr)]
+struct FixtureAttributes {
+    scope: Option<Ident>,
+    teardown: Option<Ident>,
+}
+
+// Fixtures return a Result<T, TestError>, we need T to find the value back.
+fn fixture_type(input: &ItemFn) -> Option<&Type> {
+    let ReturnType::Type(_, ref ty) = input.sig.output else {
+        return None;
+    };
+
+    let Type::Path(ref path) = **ty else {
+        return None;
+    };
+
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+
+    let PathArguments::AngleBracketed(ref args) = segment.arguments else {
+        return None;
+    };
+
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+#[proc_macro_error]
+#[proc_macro_attribute]
+pub fn cgt_fixture(args: TokenStream, item: TokenStream) -> TokenStream {
+    let attrs: FixtureAttributes = parse_macro_input!(args);
+
+    let scope = match attrs.scope {
+        None => quote! { Test },
+        Some(ref scope) if scope == "test" => quote! { Test },
+        Some(ref scope) if scope == "suite" => quote! { Suite },
+        Some(ref scope) => {
+            return syn::Error::new(scope.span(), "Expected suite or test")
+                .into_compile_error()
+                .into();
+        }
+    };
+
+    let input = parse_macro_input!(item as ItemFn);
+    let fn_ident = &input.sig.ident;
+    let fn_name = fn_ident.to_string();
+
+    let Some(ty) = fixture_type(&input) else {
+        return syn::Error::new(
+            input.sig.output.span(),
+            "Fixtures must return a Result<T, TestError>",
+        )
+        .into_compile_error()
+        .into();
+    };
+
+    let call = match input.sig.inputs.len() {
+        0 => quote! { #fn_ident() },
+        1 => quote! { #fn_ident(fd) },
+        _ => {
+            return syn::Error::new(
+                input.sig.inputs.span(),
+                "Fixtures take either no argument or the device file descriptor",
+            )
+            .into_compile_error()
+            .into();
+        }
+    };
+
+    let setup = format_ident!("__cgt_fixture_setup_{}", fn_ident);
+    let teardown_fn = format_ident!("__cgt_fixture_teardown_{}", fn_ident);
+
+    let (teardown_wrapper, teardown) = match attrs.teardown {
+        Some(ref teardown) => (
+            quote! {
+                fn #teardown_fn(value: &cgt_core::FixtureValue) -> Result<(), cgt_core::TestError> {
+                    match value.downcast_ref::<#ty>() {
+                        Some(value) => #teardown(value),
+                        None => Ok(()),
+                    }
+                }
+            },
+            quote! { Some(#teardown_fn) },
+        ),
+        None => (quote! {}, quote! { None }),
+    };
+
+    quote! {
+        #input
+
+        #[allow(unused_variables)]
+        fn #setup(fd: ::std::os::fd::BorrowedFd<'_>) -> Result<cgt_core::FixtureValue, cgt_core::TestError> {
+            #call.map(|value| ::std::sync::Arc::new(value) as cgt_core::FixtureValue)
+        }
+
+        #teardown_wrapper
+
+        inventory::submit!(
+            cgt_core::Fixture {
+                module_name: module_path!(),
+                name: #fn_name,
+                scope: cgt_core::FixtureScope::#scope,
+                setup: #setup,
+                teardown: #teardown,
             }
         );
     }
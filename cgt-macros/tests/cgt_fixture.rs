@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    os::{
+        fd::{AsFd, BorrowedFd},
+        unix::fs::FileTypeExt,
+    },
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use cgt_core::{list_tests, Fixture, FixtureScope, TestError, TestFunction, TestSelection};
+use cgt_macros::{cgt_fixture, cgt_test, cgt_test_with_fd, cgt_test_with_path};
+
+static TORN_DOWN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, PartialEq)]
+struct Mode {
+    width: u32,
+    height: u32,
+}
+
+#[cgt_fixture(scope = suite, teardown = release_mode)]
+fn mode(fd: BorrowedFd<'_>) -> Result<Mode, TestError> {
+    let file = File::from(fd.try_clone_to_owned()?);
+
+    if !file.metadata()?.file_type().is_char_device() {
+        return Err(TestError::Skipped(String::from("no device")));
+    }
+
+    Ok(Mode {
+        width: 1920,
+        height: 1080,
+    })
+}
+
+fn release_mode(mode: &Mode) -> Result<(), TestError> {
+    assert_eq!(mode.width, 1920);
+    TORN_DOWN.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+#[cgt_fixture]
+fn answer() -> Result<u32, TestError> {
+    Ok(42)
+}
+
+#[cgt_test]
+fn test_no_arg(answer: &u32) -> Result<(), TestError> {
+    assert_eq!(*answer, 42);
+    Ok(())
+}
+
+#[cgt_test_with_path]
+fn test_with_path(_path: &Path, mode: &Mode, answer: &u32) -> Result<(), TestError> {
+    assert_eq!(mode.height, 1080);
+    assert_eq!(*answer, 42);
+    Ok(())
+}
+
+#[cgt_test_with_fd(master)]
+fn test_with_fd(_fd: BorrowedFd<'_>, mode: &Mode) -> Result<(), TestError> {
+    assert_eq!(mode.width, 1920);
+    Ok(())
+}
+
+fn fixture(name: &str) -> &'static Fixture {
+    inventory::iter::<Fixture>
+        .into_iter()
+        .find(|fixture| fixture.name == name)
+        .unwrap()
+}
+
+#[test]
+fn cgt_fixture_registered() {
+    let mode = fixture("mode");
+
+    assert_eq!(mode.module_name, module_path!());
+    assert_eq!(mode.scope, FixtureScope::Suite);
+
+    let device = File::open("/dev/null").unwrap();
+    let value = (mode.setup)(device.as_fd()).unwrap();
+    assert_eq!(
+        value.downcast_ref::<Mode>(),
+        Some(&Mode {
+            width: 1920,
+            height: 1080
+        })
+    );
+
+    (mode.teardown.unwrap())(&value).unwrap();
+    assert!(TORN_DOWN.load(Ordering::SeqCst));
+
+    let not_device = File::open("Cargo.toml").unwrap();
+    assert!(matches!(
+        (mode.setup)(not_device.as_fd()),
+        Err(TestError::Skipped(_))
+    ));
+
+    let answer = fixture("answer");
+    assert_eq!(answer.scope, FixtureScope::Test);
+    assert!(answer.teardown.is_none());
+}
+
+#[test]
+fn cgt_test_fixtures() {
+    let name = |test| format!("{}::{test}", module_path!());
+    let selection = TestSelection::new()
+        .name(&name("test_no_arg"))
+        .name(&name("test_with_path"))
+        .name(&name("test_with_fd"));
+
+    let tests = list_tests(&selection);
+    assert_eq!(tests.len(), 3);
+
+    for test in tests {
+        let expected: &[&str] = match test.test_name {
+            "test_no_arg" => &["answer"],
+            "test_with_path" => &["mode", "answer"],
+            "test_with_fd" => &["mode"],
+            name => unreachable!("{name}"),
+        };
+
+        assert_eq!(
+            test.fixtures.into_iter().flatten().collect::<Vec<_>>(),
+            expected
+        );
+
+        // Outside of the runner, the fixtures aren't set up.
+        if let TestFunction::NoArg(f) = test.test_fn {
+            assert!(matches!(f(), Err(TestError::FixtureFailed(_))));
+        }
+    }
+}
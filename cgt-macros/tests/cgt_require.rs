@@ -0,0 +1,42 @@
+use cgt_core::TestError;
+use cgt_macros::cgt_require;
+
+#[test]
+fn cgt_require_bool_true() {
+    fn test() -> Result<(), TestError> {
+        cgt_require!(true);
+        Ok(())
+    }
+
+    assert_eq!(test(), Ok(()));
+}
+
+#[test]
+fn cgt_require_bool_false() {
+    fn test() -> Result<(), TestError> {
+        cgt_require!(false);
+        unreachable!()
+    }
+
+    assert_eq!(test(), Err(TestError::Skipped(String::from("false"))));
+}
+
+#[test]
+fn cgt_require_expr_true() {
+    fn test() -> Result<(), TestError> {
+        cgt_require!(1 < 2);
+        Ok(())
+    }
+
+    assert_eq!(test(), Ok(()));
+}
+
+#[test]
+fn cgt_require_expr_false() {
+    fn test() -> Result<(), TestError> {
+        cgt_require!(1 > 2);
+        unreachable!()
+    }
+
+    assert_eq!(test(), Err(TestError::Skipped(String::from("1 > 2"))));
+}
@@ -0,0 +1,6 @@
+#[cgt_macros::cgt_fixture]
+fn answer(_fd: std::os::fd::BorrowedFd<'_>, _count: u32) -> Result<u32, cgt_core::TestError> {
+    Ok(42)
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[cgt_macros::cgt_fixture(scope = run)]
+fn answer() -> Result<u32, cgt_core::TestError> {
+    Ok(42)
+}
+
+fn main() {}
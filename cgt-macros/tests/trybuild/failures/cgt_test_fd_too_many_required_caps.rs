@@ -0,0 +1,16 @@
+#[cgt_macros::cgt_test_with_fd(requires_caps = [
+    DumbBuffer,
+    VblankHighCrtc,
+    DumbPreferredDepth,
+    DumbPreferShadow,
+    Prime,
+    TimestampMonotonic,
+    AsyncPageFlip,
+    CursorWidth,
+    CursorHeight
+])]
+fn test(_: std::os::fd::BorrowedFd<'_>) -> Result<(), cgt_core::TestError> {
+    Ok(())
+}
+
+fn main() {}
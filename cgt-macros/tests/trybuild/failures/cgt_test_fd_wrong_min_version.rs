@@ -0,0 +1,6 @@
+#[cgt_macros::cgt_test_with_fd(min_version = "one")]
+fn test(_: std::os::fd::BorrowedFd<'_>) -> Result<(), cgt_core::TestError> {
+    Ok(())
+}
+
+fn main() {}
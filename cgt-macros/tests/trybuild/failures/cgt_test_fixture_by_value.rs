@@ -0,0 +1,6 @@
+#[cgt_macros::cgt_test]
+fn test(answer: u32) -> Result<(), cgt_core::TestError> {
+    Ok(())
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use drm_uapi::DriverCapability::*;
+
+#[cgt_macros::cgt_test_with_fd(
+    requires_caps = [DumbBuffer, Prime],
+    driver = "vkms",
+    min_version = "1.0"
+)]
+fn test(_: std::os::fd::BorrowedFd<'_>) -> Result<(), cgt_core::TestError> {
+    Ok(())
+}
+
+fn main() {}
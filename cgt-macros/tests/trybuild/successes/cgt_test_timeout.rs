@@ -0,0 +1,16 @@
+#[cgt_macros::cgt_test(timeout = 5)]
+fn test() -> Result<(), cgt_core::TestError> {
+    Ok(())
+}
+
+#[cgt_macros::cgt_test_with_path(timeout = 5)]
+fn test_path(_: &std::path::Path) -> Result<(), cgt_core::TestError> {
+    Ok(())
+}
+
+#[cgt_macros::cgt_test_with_fd(master, timeout = 5)]
+fn test_fd(_: std::os::fd::BorrowedFd<'_>) -> Result<(), cgt_core::TestError> {
+    Ok(())
+}
+
+fn main() {}
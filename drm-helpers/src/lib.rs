@@ -1,11 +1,85 @@
-use std::os::fd::{AsRawFd, BorrowedFd};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
 
 use drm_uapi::{
-    drm_ioctl_drop_master, drm_ioctl_set_client_cap, drm_ioctl_set_master, drm_setclientcap,
-    ClientCapability,
+    drm_auth, drm_getcap, drm_ioctl_auth_magic, drm_ioctl_drop_master, drm_ioctl_get_cap,
+    drm_ioctl_get_unique, drm_ioctl_mode_getconnector, drm_ioctl_mode_getcrtc,
+    drm_ioctl_mode_getencoder, drm_ioctl_mode_getplane, drm_ioctl_mode_getplaneresources,
+    drm_ioctl_mode_getresources, drm_ioctl_set_client_cap, drm_ioctl_set_master, drm_ioctl_version,
+    drm_mode_card_res, drm_mode_crtc, drm_mode_get_connector, drm_mode_get_encoder,
+    drm_mode_get_plane, drm_mode_get_plane_res, drm_mode_modeinfo, drm_setclientcap, drm_unique,
+    drm_version, ClientCapability, DriverCapability,
 };
 use strum::IntoEnumIterator;
 
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DriverVersion {
+    pub major: i32,
+    pub minor: i32,
+    pub patch: i32,
+    pub name: String,
+    pub date: String,
+    pub desc: String,
+}
+
+pub fn get_version(fd: BorrowedFd<'_>) -> Result<DriverVersion, std::io::Error> {
+    let mut count = drm_version::default();
+
+    unsafe { drm_ioctl_version(fd.as_raw_fd(), &mut count) }?;
+
+    let mut name: Vec<u8> = vec![0; count.name_len];
+    let mut date: Vec<u8> = vec![0; count.date_len];
+    let mut desc: Vec<u8> = vec![0; count.desc_len];
+
+    let mut data = drm_version {
+        name_len: name.len(),
+        name: array_ptr(&mut name),
+        date_len: date.len(),
+        date: array_ptr(&mut date),
+        desc_len: desc.len(),
+        desc: array_ptr(&mut desc),
+
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl_version(fd.as_raw_fd(), &mut data) }?;
+
+    // The kernel reports the full length of each string, but only copies as
+    // much as we made room for.
+    name.truncate(data.name_len);
+    date.truncate(data.date_len);
+    desc.truncate(data.desc_len);
+
+    Ok(DriverVersion {
+        major: data.major,
+        minor: data.minor,
+        patch: data.patchlevel,
+        name: String::from_utf8_lossy(&name).into_owned(),
+        date: String::from_utf8_lossy(&date).into_owned(),
+        desc: String::from_utf8_lossy(&desc).into_owned(),
+    })
+}
+
+pub fn get_unique(fd: BorrowedFd<'_>) -> Result<String, std::io::Error> {
+    let mut count = drm_unique::default();
+
+    unsafe { drm_ioctl_get_unique(fd.as_raw_fd(), &mut count) }?;
+
+    let mut unique: Vec<u8> = vec![0; count.unique_len];
+
+    let mut data = drm_unique {
+        unique_len: unique.len(),
+        unique: array_ptr(&mut unique),
+    };
+
+    unsafe { drm_ioctl_get_unique(fd.as_raw_fd(), &mut data) }?;
+
+    unique.truncate(data.unique_len);
+
+    Ok(String::from_utf8_lossy(&unique)
+        .trim_end_matches('\0')
+        .to_string())
+}
+
 pub fn set_master(fd: BorrowedFd<'_>) -> Result<(), std::io::Error> {
     unsafe { drm_ioctl_set_master(fd.as_raw_fd()) }?;
 
@@ -18,6 +92,38 @@ pub fn drop_master(fd: BorrowedFd<'_>) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+pub fn is_master(fd: BorrowedFd<'_>) -> Result<bool, std::io::Error> {
+    // There's no ioctl to ask, but only the master can authenticate clients,
+    // and no client ever gets the magic 0. The master thus gets past the
+    // permission check, only to be told the magic is invalid.
+    let auth = drm_auth { magic: 0 };
+
+    match unsafe { drm_ioctl_auth_magic(fd.as_raw_fd(), &auth) } {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            let e = std::io::Error::from(e);
+
+            match e.kind() {
+                std::io::ErrorKind::InvalidInput => Ok(true),
+                std::io::ErrorKind::PermissionDenied => Ok(false),
+                _ => Err(e),
+            }
+        }
+    }
+}
+
+pub fn get_capability(fd: BorrowedFd<'_>, cap: DriverCapability) -> Result<u64, std::io::Error> {
+    let mut data = drm_getcap {
+        capability: cap as u64,
+
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl_get_cap(fd.as_raw_fd(), &mut data) }?;
+
+    Ok(data.value)
+}
+
 fn toggle_client_capability(
     fd: BorrowedFd<'_>,
     cap: ClientCapability,
@@ -51,3 +157,583 @@ pub fn clear_client_capabilities(fd: BorrowedFd<'_>) -> Result<(), std::io::Erro
 
     Ok(())
 }
+
+// What the guard does to the file, so that tests can use a fake device
+#[derive(Clone, Copy, Debug)]
+struct ClientOps {
+    is_master: fn(BorrowedFd<'_>) -> Result<bool, std::io::Error>,
+    set_master: fn(BorrowedFd<'_>) -> Result<(), std::io::Error>,
+    drop_master: fn(BorrowedFd<'_>) -> Result<(), std::io::Error>,
+    toggle_client_capability:
+        fn(BorrowedFd<'_>, ClientCapability, bool) -> Result<(), std::io::Error>,
+}
+
+const CLIENT_OPS: ClientOps = ClientOps {
+    is_master,
+    set_master,
+    drop_master,
+    toggle_client_capability,
+};
+
+/// Undoes the changes made through it to the master status and client
+/// capabilities of a file when dropped, so that the file can be used again
+/// as if nothing happened.
+///
+/// Client capabilities can't be queried, so the guard assumes none are
+/// enabled unless told otherwise with [`ClientStateGuard::with_capabilities`].
+///
+/// The guard either borrows the file, or owns it so that several users can
+/// share a long-lived file, e.g. the tests of a suite through a fixture, and
+/// have it restored once the last of them is done.
+#[derive(Debug)]
+pub struct ClientStateGuard<F: AsFd> {
+    fd: F,
+    ops: ClientOps,
+    master: bool,
+    initial_capabilities: Vec<ClientCapability>,
+    capabilities: Vec<ClientCapability>,
+}
+
+impl<F: AsFd> ClientStateGuard<F> {
+    pub fn new(fd: F) -> Self {
+        Self::with_capabilities(fd, &[])
+    }
+
+    pub fn with_capabilities(fd: F, enabled: &[ClientCapability]) -> Self {
+        Self {
+            fd,
+            ops: CLIENT_OPS,
+            master: false,
+            initial_capabilities: enabled.to_vec(),
+            capabilities: Vec::new(),
+        }
+    }
+
+    pub fn set_master(&mut self) -> Result<(), std::io::Error> {
+        // If the file already is master, possibly because it's the first one
+        // opened on the device, it isn't up to us to drop it.
+        if self.master || (self.ops.is_master)(self.fd.as_fd())? {
+            return Ok(());
+        }
+
+        (self.ops.set_master)(self.fd.as_fd())?;
+        self.master = true;
+
+        Ok(())
+    }
+
+    pub fn set_client_capability(&mut self, cap: ClientCapability) -> Result<(), std::io::Error> {
+        (self.ops.toggle_client_capability)(self.fd.as_fd(), cap, true)?;
+
+        if !self.initial_capabilities.contains(&cap) && !self.capabilities.contains(&cap) {
+            self.capabilities.push(cap);
+        }
+
+        Ok(())
+    }
+
+    /// Undoes everything, even if some of it fails, and returns the first
+    /// error.
+    pub fn restore(&mut self) -> Result<(), std::io::Error> {
+        let mut res = Ok(());
+
+        // Some capabilities can only be enabled on top of others, such as
+        // the writeback connectors on top of atomic, and the kernel won't
+        // let us clear them once their dependency is gone. Undoing them in
+        // the reverse order they were enabled in always works.
+        while let Some(cap) = self.capabilities.pop() {
+            res = res.and((self.ops.toggle_client_capability)(
+                self.fd.as_fd(),
+                cap,
+                false,
+            ));
+        }
+
+        if self.master {
+            self.master = false;
+            res = res.and((self.ops.drop_master)(self.fd.as_fd()));
+        }
+
+        res
+    }
+}
+
+impl<F: AsFd> AsFd for ClientStateGuard<F> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl<F: AsFd> Drop for ClientStateGuard<F> {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+fn alloc_array<T: Clone + Default>(count: u32) -> Vec<T> {
+    vec![T::default(); count as usize]
+}
+
+fn array_ptr<T>(array: &mut [T]) -> u64 {
+    array.as_mut_ptr() as u64
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Resources {
+    pub fbs: Vec<u32>,
+    pub crtcs: Vec<u32>,
+    pub connectors: Vec<u32>,
+    pub encoders: Vec<u32>,
+    pub min_width: u32,
+    pub max_width: u32,
+    pub min_height: u32,
+    pub max_height: u32,
+}
+
+pub fn get_resources(fd: BorrowedFd<'_>) -> Result<Resources, std::io::Error> {
+    loop {
+        let mut count = drm_mode_card_res::default();
+
+        unsafe { drm_ioctl_mode_getresources(fd.as_raw_fd(), &mut count) }?;
+
+        let mut fbs = alloc_array(count.count_fbs);
+        let mut crtcs = alloc_array(count.count_crtcs);
+        let mut connectors = alloc_array(count.count_connectors);
+        let mut encoders = alloc_array(count.count_encoders);
+
+        let mut data = drm_mode_card_res {
+            fb_id_ptr: array_ptr(&mut fbs),
+            crtc_id_ptr: array_ptr(&mut crtcs),
+            connector_id_ptr: array_ptr(&mut connectors),
+            encoder_id_ptr: array_ptr(&mut encoders),
+            count_fbs: count.count_fbs,
+            count_crtcs: count.count_crtcs,
+            count_connectors: count.count_connectors,
+            count_encoders: count.count_encoders,
+
+            ..Default::default()
+        };
+
+        unsafe { drm_ioctl_mode_getresources(fd.as_raw_fd(), &mut data) }?;
+
+        // Objects might have been added between the two calls, in which case
+        // our arrays were too small and we need to start over.
+        if data.count_fbs > count.count_fbs
+            || data.count_crtcs > count.count_crtcs
+            || data.count_connectors > count.count_connectors
+            || data.count_encoders > count.count_encoders
+        {
+            continue;
+        }
+
+        fbs.truncate(data.count_fbs as usize);
+        crtcs.truncate(data.count_crtcs as usize);
+        connectors.truncate(data.count_connectors as usize);
+        encoders.truncate(data.count_encoders as usize);
+
+        return Ok(Resources {
+            fbs,
+            crtcs,
+            connectors,
+            encoders,
+            min_width: data.min_width,
+            max_width: data.max_width,
+            min_height: data.min_height,
+            max_height: data.max_height,
+        });
+    }
+}
+
+pub fn get_plane_ids(fd: BorrowedFd<'_>) -> Result<Vec<u32>, std::io::Error> {
+    loop {
+        let mut count = drm_mode_get_plane_res::default();
+
+        unsafe { drm_ioctl_mode_getplaneresources(fd.as_raw_fd(), &mut count) }?;
+
+        let mut planes = alloc_array(count.count_planes);
+
+        let mut data = drm_mode_get_plane_res {
+            plane_id_ptr: array_ptr(&mut planes),
+            count_planes: count.count_planes,
+        };
+
+        unsafe { drm_ioctl_mode_getplaneresources(fd.as_raw_fd(), &mut data) }?;
+
+        if data.count_planes > count.count_planes {
+            continue;
+        }
+
+        planes.truncate(data.count_planes as usize);
+
+        return Ok(planes);
+    }
+}
+
+pub fn get_crtc_ids(fd: BorrowedFd<'_>) -> Result<Vec<u32>, std::io::Error> {
+    Ok(get_resources(fd)?.crtcs)
+}
+
+pub fn get_encoder_ids(fd: BorrowedFd<'_>) -> Result<Vec<u32>, std::io::Error> {
+    Ok(get_resources(fd)?.encoders)
+}
+
+pub fn get_connector_ids(fd: BorrowedFd<'_>) -> Result<Vec<u32>, std::io::Error> {
+    Ok(get_resources(fd)?.connectors)
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plane {
+    pub id: u32,
+    pub crtc_id: u32,
+    pub fb_id: u32,
+    pub possible_crtcs: u32,
+    pub gamma_size: u32,
+    pub formats: Vec<u32>,
+}
+
+pub fn get_plane(fd: BorrowedFd<'_>, id: u32) -> Result<Plane, std::io::Error> {
+    loop {
+        let mut count = drm_mode_get_plane {
+            plane_id: id,
+
+            ..Default::default()
+        };
+
+        unsafe { drm_ioctl_mode_getplane(fd.as_raw_fd(), &mut count) }?;
+
+        let mut formats = alloc_array(count.count_format_types);
+
+        let mut data = drm_mode_get_plane {
+            plane_id: id,
+            count_format_types: count.count_format_types,
+            format_type_ptr: array_ptr(&mut formats),
+
+            ..Default::default()
+        };
+
+        unsafe { drm_ioctl_mode_getplane(fd.as_raw_fd(), &mut data) }?;
+
+        if data.count_format_types > count.count_format_types {
+            continue;
+        }
+
+        formats.truncate(data.count_format_types as usize);
+
+        return Ok(Plane {
+            id: data.plane_id,
+            crtc_id: data.crtc_id,
+            fb_id: data.fb_id,
+            possible_crtcs: data.possible_crtcs,
+            gamma_size: data.gamma_size,
+            formats,
+        });
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Crtc {
+    pub id: u32,
+    pub fb_id: u32,
+    pub x: u32,
+    pub y: u32,
+    pub gamma_size: u32,
+    pub mode: Option<drm_mode_modeinfo>,
+}
+
+pub fn get_crtc(fd: BorrowedFd<'_>, id: u32) -> Result<Crtc, std::io::Error> {
+    let mut data = drm_mode_crtc {
+        crtc_id: id,
+
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl_mode_getcrtc(fd.as_raw_fd(), &mut data) }?;
+
+    Ok(Crtc {
+        id: data.crtc_id,
+        fb_id: data.fb_id,
+        x: data.x,
+        y: data.y,
+        gamma_size: data.gamma_size,
+        mode: if data.mode_valid != 0 {
+            Some(data.mode)
+        } else {
+            None
+        },
+    })
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Encoder {
+    pub id: u32,
+    pub encoder_type: u32,
+    pub crtc_id: u32,
+    pub possible_crtcs: u32,
+    pub possible_clones: u32,
+}
+
+pub fn get_encoder(fd: BorrowedFd<'_>, id: u32) -> Result<Encoder, std::io::Error> {
+    let mut data = drm_mode_get_encoder {
+        encoder_id: id,
+
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl_mode_getencoder(fd.as_raw_fd(), &mut data) }?;
+
+    Ok(Encoder {
+        id: data.encoder_id,
+        encoder_type: data.encoder_type,
+        crtc_id: data.crtc_id,
+        possible_crtcs: data.possible_crtcs,
+        possible_clones: data.possible_clones,
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Connector {
+    pub id: u32,
+    pub connector_type: u32,
+    pub connector_type_id: u32,
+    pub connection: u32,
+    pub encoder_id: u32,
+    pub mm_width: u32,
+    pub mm_height: u32,
+    pub subpixel: u32,
+    pub encoders: Vec<u32>,
+    pub modes: Vec<drm_mode_modeinfo>,
+    pub props: Vec<u32>,
+    pub prop_values: Vec<u64>,
+}
+
+pub fn get_connector(fd: BorrowedFd<'_>, id: u32) -> Result<Connector, std::io::Error> {
+    loop {
+        let mut count = drm_mode_get_connector {
+            connector_id: id,
+
+            ..Default::default()
+        };
+
+        unsafe { drm_ioctl_mode_getconnector(fd.as_raw_fd(), &mut count) }?;
+
+        let mut encoders = alloc_array(count.count_encoders);
+        let mut modes = alloc_array(count.count_modes);
+        let mut props = alloc_array(count.count_props);
+        let mut prop_values = alloc_array(count.count_props);
+
+        let mut data = drm_mode_get_connector {
+            encoders_ptr: array_ptr(&mut encoders),
+            modes_ptr: array_ptr(&mut modes),
+            props_ptr: array_ptr(&mut props),
+            prop_values_ptr: array_ptr(&mut prop_values),
+            count_modes: count.count_modes,
+            count_props: count.count_props,
+            count_encoders: count.count_encoders,
+            connector_id: id,
+
+            ..Default::default()
+        };
+
+        unsafe { drm_ioctl_mode_getconnector(fd.as_raw_fd(), &mut data) }?;
+
+        // The mode list can change between the two calls if the connector
+        // was probed again, so we need to retry if it grew.
+        if data.count_encoders > count.count_encoders
+            || data.count_modes > count.count_modes
+            || data.count_props > count.count_props
+        {
+            continue;
+        }
+
+        encoders.truncate(data.count_encoders as usize);
+        modes.truncate(data.count_modes as usize);
+        props.truncate(data.count_props as usize);
+        prop_values.truncate(data.count_props as usize);
+
+        return Ok(Connector {
+            id: data.connector_id,
+            connector_type: data.connector_type,
+            connector_type_id: data.connector_type_id,
+            connection: data.connection,
+            encoder_id: data.encoder_id,
+            mm_width: data.mm_width,
+            mm_height: data.mm_height,
+            subpixel: data.subpixel,
+            encoders,
+            modes,
+            props,
+            prop_values,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        fs::File,
+        io,
+        os::fd::{AsFd, AsRawFd},
+    };
+
+    use drm_uapi::ClientCapability;
+
+    use super::{ClientOps, ClientStateGuard};
+
+    // Client state of a fake file, following the rules of the kernel.
+    #[derive(Default)]
+    struct FakeFile {
+        master: bool,
+        capabilities: Vec<ClientCapability>,
+        broken: Option<ClientCapability>,
+    }
+
+    thread_local! {
+        static FAKE: RefCell<FakeFile> = RefCell::new(FakeFile::default());
+    }
+
+    fn fake<R>(f: impl FnOnce(&mut FakeFile) -> R) -> R {
+        FAKE.with(|fake| f(&mut fake.borrow_mut()))
+    }
+
+    const FAKE_OPS: ClientOps = ClientOps {
+        is_master: |_| Ok(fake(|fake| fake.master)),
+        set_master: |_| {
+            fake(|fake| fake.master = true);
+            Ok(())
+        },
+        drop_master: |_| {
+            fake(|fake| {
+                if !fake.master {
+                    return Err(io::ErrorKind::InvalidInput.into());
+                }
+
+                fake.master = false;
+                Ok(())
+            })
+        },
+        toggle_client_capability: |_, cap, enable| {
+            fake(|fake| {
+                if !enable && fake.broken == Some(cap) {
+                    return Err(io::ErrorKind::Other.into());
+                }
+
+                if cap == ClientCapability::WritebackConnectors
+                    && !fake.capabilities.contains(&ClientCapability::Atomic)
+                {
+                    return Err(io::ErrorKind::InvalidInput.into());
+                }
+
+                let mut caps = vec![cap];
+
+                if cap == ClientCapability::Atomic {
+                    caps.extend([
+                        ClientCapability::UniversalPlanes,
+                        ClientCapability::AspectRatio,
+                    ]);
+                }
+
+                fake.capabilities.retain(|c| !caps.contains(c));
+
+                if enable {
+                    fake.capabilities.extend(caps);
+                }
+
+                Ok(())
+            })
+        },
+    };
+
+    fn guard<F: AsFd>(fd: F, enabled: &[ClientCapability]) -> ClientStateGuard<F> {
+        let mut guard = ClientStateGuard::with_capabilities(fd, enabled);
+
+        guard.ops = FAKE_OPS;
+        guard
+    }
+
+    #[test]
+    fn restore_in_reverse_order() {
+        let file = File::open("/dev/null").unwrap();
+        let mut state = guard(file.as_fd(), &[]);
+
+        state.set_master().unwrap();
+        state
+            .set_client_capability(ClientCapability::Atomic)
+            .unwrap();
+        state
+            .set_client_capability(ClientCapability::WritebackConnectors)
+            .unwrap();
+        assert!(fake(|fake| fake.master));
+
+        // Clearing atomic first would leave the writeback connectors stuck.
+        state.restore().unwrap();
+        assert!(fake(|fake| !fake.master && fake.capabilities.is_empty()));
+    }
+
+    #[test]
+    fn keep_initial_state() {
+        fake(|fake| {
+            fake.master = true;
+            fake.capabilities = vec![ClientCapability::Stereo3d];
+        });
+
+        let file = File::open("/dev/null").unwrap();
+
+        {
+            let mut state = guard(file.as_fd(), &[ClientCapability::Stereo3d]);
+
+            state.set_master().unwrap();
+            state
+                .set_client_capability(ClientCapability::Stereo3d)
+                .unwrap();
+            state
+                .set_client_capability(ClientCapability::UniversalPlanes)
+                .unwrap();
+        }
+
+        assert!(fake(|fake| fake.master));
+        assert_eq!(
+            fake(|fake| fake.capabilities.clone()),
+            vec![ClientCapability::Stereo3d]
+        );
+    }
+
+    #[test]
+    fn restore_everything_on_error() {
+        fake(|fake| fake.broken = Some(ClientCapability::AspectRatio));
+
+        let file = File::open("/dev/null").unwrap();
+        let mut state = guard(file.as_fd(), &[]);
+
+        state.set_master().unwrap();
+        state
+            .set_client_capability(ClientCapability::Stereo3d)
+            .unwrap();
+        state
+            .set_client_capability(ClientCapability::AspectRatio)
+            .unwrap();
+
+        assert_eq!(state.restore().unwrap_err().kind(), io::ErrorKind::Other);
+        assert!(fake(|fake| !fake.master));
+        assert_eq!(
+            fake(|fake| fake.capabilities.clone()),
+            vec![ClientCapability::AspectRatio]
+        );
+    }
+
+    #[test]
+    fn owned_file() {
+        let mut state = guard(File::open("/dev/null").unwrap(), &[]);
+
+        state.set_master().unwrap();
+        state
+            .set_client_capability(ClientCapability::Atomic)
+            .unwrap();
+
+        // Whoever holds the guard can keep using the file through it.
+        assert!(state.as_fd().as_raw_fd() >= 0);
+
+        drop(state);
+        assert!(fake(|fake| !fake.master && fake.capabilities.is_empty()));
+    }
+}
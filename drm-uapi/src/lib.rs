@@ -3,11 +3,17 @@ use strum_macros::EnumIter;
 
 const DRM_IOCTL_BASE: u32 = 'd' as u32;
 const DRM_IOCTL_VERSION: u32 = 0x00;
+const DRM_IOCTL_GET_UNIQUE: u32 = 0x01;
 const DRM_IOCTL_GET_CAP: u32 = 0x0c;
 const DRM_IOCTL_SET_CLIENT_CAP: u32 = 0x0d;
+const DRM_IOCTL_AUTH_MAGIC: u32 = 0x11;
 const DRM_IOCTL_SET_MASTER: u32 = 0x1e;
 const DRM_IOCTL_DROP_MASTER: u32 = 0x1f;
 const DRM_IOCTL_ATTACH_MODE: u32 = 0xa8;
+const DRM_IOCTL_MODE_GETRESOURCES: u32 = 0xa0;
+const DRM_IOCTL_MODE_GETCRTC: u32 = 0xa1;
+const DRM_IOCTL_MODE_GETENCODER: u32 = 0xa6;
+const DRM_IOCTL_MODE_GETCONNECTOR: u32 = 0xa7;
 const DRM_IOCTL_DETACH_MODE: u32 = 0xa9;
 const DRM_IOCTL_MODE_GETPLANERESOURCES: u32 = 0xb5;
 const DRM_IOCTL_MODE_GETPLANE: u32 = 0xb6;
@@ -33,6 +39,20 @@ ioctl_readwrite!(
     drm_version
 );
 
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct drm_unique {
+    pub unique_len: usize,
+    pub unique: u64,
+}
+
+ioctl_readwrite!(
+    drm_ioctl_get_unique,
+    DRM_IOCTL_BASE,
+    DRM_IOCTL_GET_UNIQUE,
+    drm_unique
+);
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct drm_getcap {
@@ -40,6 +60,26 @@ pub struct drm_getcap {
     pub value: u64,
 }
 
+#[derive(Clone, Copy, Debug, EnumIter, PartialEq)]
+#[repr(u64)]
+pub enum DriverCapability {
+    DumbBuffer = 0x1,
+    VblankHighCrtc = 0x2,
+    DumbPreferredDepth = 0x3,
+    DumbPreferShadow = 0x4,
+    Prime = 0x5,
+    TimestampMonotonic = 0x6,
+    AsyncPageFlip = 0x7,
+    CursorWidth = 0x8,
+    CursorHeight = 0x9,
+    Addfb2Modifiers = 0x10,
+    PageFlipTarget = 0x11,
+    CrtcInVblankEvent = 0x12,
+    Syncobj = 0x13,
+    SyncobjTimeline = 0x14,
+    AtomicAsyncPageFlip = 0x15,
+}
+
 ioctl_readwrite!(
     drm_ioctl_get_cap,
     DRM_IOCTL_BASE,
@@ -71,6 +111,19 @@ ioctl_write_ptr!(
     drm_setclientcap
 );
 
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct drm_auth {
+    pub magic: u32,
+}
+
+ioctl_write_ptr!(
+    drm_ioctl_auth_magic,
+    DRM_IOCTL_BASE,
+    DRM_IOCTL_AUTH_MAGIC,
+    drm_auth
+);
+
 ioctl_none!(drm_ioctl_set_master, DRM_IOCTL_BASE, DRM_IOCTL_SET_MASTER);
 
 ioctl_none!(drm_ioctl_drop_master, DRM_IOCTL_BASE, DRM_IOCTL_DROP_MASTER);
@@ -79,6 +132,118 @@ ioctl_none!(drm_ioctl_attach_mode, DRM_IOCTL_BASE, DRM_IOCTL_ATTACH_MODE);
 
 ioctl_none!(drm_ioctl_detach_mode, DRM_IOCTL_BASE, DRM_IOCTL_DETACH_MODE);
 
+pub const DRM_DISPLAY_MODE_LEN: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_modeinfo {
+    pub clock: u32,
+    pub hdisplay: u16,
+    pub hsync_start: u16,
+    pub hsync_end: u16,
+    pub htotal: u16,
+    pub hskew: u16,
+    pub vdisplay: u16,
+    pub vsync_start: u16,
+    pub vsync_end: u16,
+    pub vtotal: u16,
+    pub vscan: u16,
+    pub vrefresh: u32,
+    pub flags: u32,
+    pub type_: u32,
+    pub name: [u8; DRM_DISPLAY_MODE_LEN],
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct drm_mode_card_res {
+    pub fb_id_ptr: u64,
+    pub crtc_id_ptr: u64,
+    pub connector_id_ptr: u64,
+    pub encoder_id_ptr: u64,
+    pub count_fbs: u32,
+    pub count_crtcs: u32,
+    pub count_connectors: u32,
+    pub count_encoders: u32,
+    pub min_width: u32,
+    pub max_width: u32,
+    pub min_height: u32,
+    pub max_height: u32,
+}
+
+ioctl_readwrite!(
+    drm_ioctl_mode_getresources,
+    DRM_IOCTL_BASE,
+    DRM_IOCTL_MODE_GETRESOURCES,
+    drm_mode_card_res
+);
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct drm_mode_crtc {
+    pub set_connectors_ptr: u64,
+    pub count_connectors: u32,
+    pub crtc_id: u32,
+    pub fb_id: u32,
+    pub x: u32,
+    pub y: u32,
+    pub gamma_size: u32,
+    pub mode_valid: u32,
+    pub mode: drm_mode_modeinfo,
+}
+
+ioctl_readwrite!(
+    drm_ioctl_mode_getcrtc,
+    DRM_IOCTL_BASE,
+    DRM_IOCTL_MODE_GETCRTC,
+    drm_mode_crtc
+);
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct drm_mode_get_encoder {
+    pub encoder_id: u32,
+    pub encoder_type: u32,
+    pub crtc_id: u32,
+    pub possible_crtcs: u32,
+    pub possible_clones: u32,
+}
+
+ioctl_readwrite!(
+    drm_ioctl_mode_getencoder,
+    DRM_IOCTL_BASE,
+    DRM_IOCTL_MODE_GETENCODER,
+    drm_mode_get_encoder
+);
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct drm_mode_get_connector {
+    pub encoders_ptr: u64,
+    pub modes_ptr: u64,
+    pub props_ptr: u64,
+    pub prop_values_ptr: u64,
+    pub count_modes: u32,
+    pub count_props: u32,
+    pub count_encoders: u32,
+    pub encoder_id: u32,
+    pub connector_id: u32,
+    pub connector_type: u32,
+    pub connector_type_id: u32,
+    pub connection: u32,
+    pub mm_width: u32,
+    pub mm_height: u32,
+    pub subpixel: u32,
+    pub pad: u32,
+}
+
+ioctl_readwrite!(
+    drm_ioctl_mode_getconnector,
+    DRM_IOCTL_BASE,
+    DRM_IOCTL_MODE_GETCONNECTOR,
+    drm_mode_get_connector
+);
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct drm_mode_get_plane_res {
@@ -111,3 +276,124 @@ ioctl_readwrite!(
     DRM_IOCTL_MODE_GETPLANE,
     drm_mode_get_plane
 );
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use super::*;
+
+    // Sizes and offsets below come from include/uapi/drm/drm.h and
+    // include/uapi/drm/drm_mode.h for a 64-bit kernel.
+
+    #[test]
+    fn drm_version_layout() {
+        assert_eq!(size_of::<drm_version>(), 64);
+        assert_eq!(offset_of!(drm_version, name_len), 16);
+        assert_eq!(offset_of!(drm_version, name), 24);
+        assert_eq!(offset_of!(drm_version, date_len), 32);
+        assert_eq!(offset_of!(drm_version, date), 40);
+        assert_eq!(offset_of!(drm_version, desc_len), 48);
+        assert_eq!(offset_of!(drm_version, desc), 56);
+    }
+
+    #[test]
+    fn drm_unique_layout() {
+        assert_eq!(size_of::<drm_unique>(), 16);
+        assert_eq!(offset_of!(drm_unique, unique), 8);
+    }
+
+    #[test]
+    fn drm_getcap_layout() {
+        assert_eq!(size_of::<drm_getcap>(), 16);
+        assert_eq!(offset_of!(drm_getcap, value), 8);
+    }
+
+    #[test]
+    fn drm_setclientcap_layout() {
+        assert_eq!(size_of::<drm_setclientcap>(), 16);
+        assert_eq!(offset_of!(drm_setclientcap, value), 8);
+    }
+
+    #[test]
+    fn drm_mode_modeinfo_layout() {
+        assert_eq!(size_of::<drm_mode_modeinfo>(), 68);
+        assert_eq!(offset_of!(drm_mode_modeinfo, hdisplay), 4);
+        assert_eq!(offset_of!(drm_mode_modeinfo, hskew), 12);
+        assert_eq!(offset_of!(drm_mode_modeinfo, vdisplay), 14);
+        assert_eq!(offset_of!(drm_mode_modeinfo, vscan), 22);
+        assert_eq!(offset_of!(drm_mode_modeinfo, vrefresh), 24);
+        assert_eq!(offset_of!(drm_mode_modeinfo, flags), 28);
+        assert_eq!(offset_of!(drm_mode_modeinfo, type_), 32);
+        assert_eq!(offset_of!(drm_mode_modeinfo, name), 36);
+    }
+
+    #[test]
+    fn drm_mode_card_res_layout() {
+        assert_eq!(size_of::<drm_mode_card_res>(), 64);
+        assert_eq!(offset_of!(drm_mode_card_res, crtc_id_ptr), 8);
+        assert_eq!(offset_of!(drm_mode_card_res, connector_id_ptr), 16);
+        assert_eq!(offset_of!(drm_mode_card_res, encoder_id_ptr), 24);
+        assert_eq!(offset_of!(drm_mode_card_res, count_fbs), 32);
+        assert_eq!(offset_of!(drm_mode_card_res, count_crtcs), 36);
+        assert_eq!(offset_of!(drm_mode_card_res, count_connectors), 40);
+        assert_eq!(offset_of!(drm_mode_card_res, count_encoders), 44);
+        assert_eq!(offset_of!(drm_mode_card_res, min_width), 48);
+        assert_eq!(offset_of!(drm_mode_card_res, max_height), 60);
+    }
+
+    #[test]
+    fn drm_mode_crtc_layout() {
+        assert_eq!(size_of::<drm_mode_crtc>(), 104);
+        assert_eq!(offset_of!(drm_mode_crtc, count_connectors), 8);
+        assert_eq!(offset_of!(drm_mode_crtc, crtc_id), 12);
+        assert_eq!(offset_of!(drm_mode_crtc, fb_id), 16);
+        assert_eq!(offset_of!(drm_mode_crtc, x), 20);
+        assert_eq!(offset_of!(drm_mode_crtc, y), 24);
+        assert_eq!(offset_of!(drm_mode_crtc, gamma_size), 28);
+        assert_eq!(offset_of!(drm_mode_crtc, mode_valid), 32);
+        assert_eq!(offset_of!(drm_mode_crtc, mode), 36);
+    }
+
+    #[test]
+    fn drm_mode_get_encoder_layout() {
+        assert_eq!(size_of::<drm_mode_get_encoder>(), 20);
+        assert_eq!(offset_of!(drm_mode_get_encoder, encoder_type), 4);
+        assert_eq!(offset_of!(drm_mode_get_encoder, crtc_id), 8);
+        assert_eq!(offset_of!(drm_mode_get_encoder, possible_crtcs), 12);
+        assert_eq!(offset_of!(drm_mode_get_encoder, possible_clones), 16);
+    }
+
+    #[test]
+    fn drm_mode_get_connector_layout() {
+        assert_eq!(size_of::<drm_mode_get_connector>(), 80);
+        assert_eq!(offset_of!(drm_mode_get_connector, modes_ptr), 8);
+        assert_eq!(offset_of!(drm_mode_get_connector, props_ptr), 16);
+        assert_eq!(offset_of!(drm_mode_get_connector, prop_values_ptr), 24);
+        assert_eq!(offset_of!(drm_mode_get_connector, count_modes), 32);
+        assert_eq!(offset_of!(drm_mode_get_connector, count_props), 36);
+        assert_eq!(offset_of!(drm_mode_get_connector, count_encoders), 40);
+        assert_eq!(offset_of!(drm_mode_get_connector, encoder_id), 44);
+        assert_eq!(offset_of!(drm_mode_get_connector, connector_id), 48);
+        assert_eq!(offset_of!(drm_mode_get_connector, connector_type), 52);
+        assert_eq!(offset_of!(drm_mode_get_connector, connector_type_id), 56);
+        assert_eq!(offset_of!(drm_mode_get_connector, connection), 60);
+        assert_eq!(offset_of!(drm_mode_get_connector, mm_width), 64);
+        assert_eq!(offset_of!(drm_mode_get_connector, mm_height), 68);
+        assert_eq!(offset_of!(drm_mode_get_connector, subpixel), 72);
+    }
+
+    #[test]
+    fn drm_mode_get_plane_res_layout() {
+        assert_eq!(size_of::<drm_mode_get_plane_res>(), 16);
+        assert_eq!(offset_of!(drm_mode_get_plane_res, count_planes), 8);
+    }
+
+    #[test]
+    fn drm_mode_get_plane_layout() {
+        assert_eq!(size_of::<drm_mode_get_plane>(), 32);
+        assert_eq!(offset_of!(drm_mode_get_plane, possible_crtcs), 12);
+        assert_eq!(offset_of!(drm_mode_get_plane, count_format_types), 20);
+        assert_eq!(offset_of!(drm_mode_get_plane, format_type_ptr), 24);
+    }
+}
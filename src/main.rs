@@ -7,58 +7,241 @@
 #![allow(clippy::unnecessary_wraps)]
 #![doc = include_str!("../README.md")]
 
-use colored::Colorize;
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use cgt_core::{run_all, DeviceSpecifier, RunResult, Test, TestError, TestResultWriter};
+use cgt_core::{
+    compare_results, list_tests, read_json_results, run_all, DeviceError, DeviceSpecifier,
+    Expectation, Expectations, ExpectedOutcome, IgtResultWriter, JUnitResultWriter,
+    JsonResultWriter, KernelLogPolicy, KernelMessage, Pattern, RunError, RunOptions, RunResult,
+    RunWarning, Taint, TapResultWriter, Test, TestError, TestResultWriter, TestSelection, Verdict,
+    DEFAULT_KMSG_PATH, DEFAULT_TAINT_PATH, DEFAULT_TIMEOUT,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use colored::{ColoredString, Colorize};
+use drm_helpers::DriverVersion;
 
 mod tests;
 
-#[derive(Default)]
+/// Tests taking longer than this are reported as slow
+const SLOW_TEST_THRESHOLD: Duration = Duration::from_secs(1);
+
+#[allow(clippy::struct_field_names)]
 struct ConsoleResultWriter {
+    output: Box<dyn Write>,
     num_tests: usize,
     successful_tests: usize,
     failing_tests: usize,
+    skipped_tests: usize,
+    timed_out_tests: usize,
+    warning_tests: usize,
+    unexpected_tests: usize,
+    kernel_warnings: Vec<String>,
+    taint: Option<Taint>,
+    verdict: Option<ColoredString>,
+    in_test: bool,
+    warnings: Vec<String>,
+}
+
+impl ConsoleResultWriter {
+    fn with_output(output: Box<dyn Write>) -> Self {
+        Self {
+            output,
+            num_tests: 0,
+            successful_tests: 0,
+            failing_tests: 0,
+            skipped_tests: 0,
+            timed_out_tests: 0,
+            warning_tests: 0,
+            unexpected_tests: 0,
+            kernel_warnings: Vec::new(),
+            taint: None,
+            verdict: None,
+            in_test: false,
+            warnings: Vec::new(),
+        }
+    }
 }
 
 impl TestResultWriter for ConsoleResultWriter {
     fn new() -> Self {
-        Self::default()
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    fn write_device_error(&mut self, err: &DeviceError) {
+        let _ = writeln!(self.output, "{}", err.to_string().red().bold());
+    }
+
+    fn write_run_error(&mut self, err: &RunError) {
+        let _ = writeln!(self.output, "{}", err.to_string().red().bold());
+    }
+
+    // The line of a running test is only complete once we have its result.
+    fn write_warning(&mut self, warning: &RunWarning) {
+        if self.in_test {
+            self.warnings.push(warning.to_string());
+        } else {
+            let _ = writeln!(self.output, "{}", warning.to_string().yellow().bold());
+        }
+    }
+
+    fn start_device(&mut self, device: &Path, version: Option<&DriverVersion>) {
+        let _ = match version {
+            Some(version) => writeln!(
+                self.output,
+                "Testing {} ({} {}.{}.{} {}, {})",
+                device.display(),
+                version.name.bold(),
+                version.major,
+                version.minor,
+                version.patch,
+                version.date,
+                version.desc,
+            ),
+            None => writeln!(self.output, "Testing {}", device.display()),
+        };
+    }
+
+    fn end_device(&mut self) {
+        let _ = writeln!(self.output);
     }
 
     fn start_suite(&mut self, name: &str, tests: &[Test]) {
-        println!("\nRunning {} ({} tests)\n", name, tests.len());
+        let _ = writeln!(self.output, "\nRunning {} ({} tests)\n", name, tests.len());
     }
 
     fn write_test(&mut self, test: &Test) {
-        print!("    {}", test.test_name.bold());
+        let _ = write!(self.output, "    {}", test.test_name.bold());
+        let _ = self.output.flush();
         self.num_tests += 1;
+        self.in_test = true;
     }
 
-    fn write_result(&mut self, _test: &Test, res: &Result<(), TestError>) {
+    fn write_result(&mut self, _test: &Test, res: &Result<(), TestError>, duration: Duration) {
+        let slow = if duration >= SLOW_TEST_THRESHOLD && !matches!(res, Err(TestError::Timeout(_)))
+        {
+            format!(
+                " {}",
+                format!("(slow: {:.2}s)", duration.as_secs_f64()).yellow()
+            )
+        } else {
+            String::new()
+        };
+
         match res {
             Ok(()) => {
-                println!("\t{}", "✔".green().bold());
+                let _ = writeln!(self.output, "\t{}{slow}", "✔".green().bold());
                 self.successful_tests += 1;
             }
+            Err(TestError::Skipped(reason)) => {
+                let _ = writeln!(
+                    self.output,
+                    "\t{}{slow}",
+                    format!("- skipped ({reason})").yellow().bold()
+                );
+                self.skipped_tests += 1;
+            }
+            Err(e @ TestError::DmesgWarn(_)) => {
+                let _ = writeln!(
+                    self.output,
+                    "\t{}{slow}",
+                    format!("⚠ -> {e}").yellow().bold()
+                );
+                self.warning_tests += 1;
+            }
+            Err(e @ TestError::Timeout(_)) => {
+                let _ = writeln!(self.output, "\t{}", format!("⏱ -> {e}").red().bold());
+                self.timed_out_tests += 1;
+            }
             Err(e) => {
-                println!("\t{}", format!("✘ -> {e}").red().bold());
+                let _ = writeln!(self.output, "\t{}{slow}", format!("✘ -> {e}").red().bold());
                 self.failing_tests += 1;
             }
         }
+
+        for line in self.kernel_warnings.drain(..) {
+            let _ = writeln!(self.output, "        {}", line.yellow());
+        }
+
+        if let Some(taint) = self.taint.take() {
+            let _ = writeln!(
+                self.output,
+                "        {}",
+                format!("Kernel tainted: {taint}").red().bold()
+            );
+        }
+
+        if let Some(verdict) = self.verdict.take() {
+            let _ = writeln!(self.output, "        {verdict}");
+        }
+
+        for warning in self.warnings.drain(..) {
+            let _ = writeln!(self.output, "        {}", warning.yellow().bold());
+        }
+
+        self.in_test = false;
     }
 
-    fn end_suite(&mut self) {
-        println!(
+    fn write_kernel_log(&mut self, _test: &Test, messages: &[KernelMessage]) {
+        self.kernel_warnings = messages
+            .iter()
+            .filter(|msg| msg.is_warning())
+            .map(ToString::to_string)
+            .collect();
+    }
+
+    fn write_taint(&mut self, _test: &Test, taint: Taint) {
+        self.taint = Some(taint);
+    }
+
+    fn write_verdict(&mut self, _test: &Test, verdict: Verdict, expected: Option<&Expectation>) {
+        let comment = expected
+            .and_then(|e| e.comment.as_deref())
+            .map(|comment| format!(" ({comment})"))
+            .unwrap_or_default();
+
+        self.verdict = match (verdict, expected.map(|e| e.outcome)) {
+            (Verdict::Expected, Some(ExpectedOutcome::Fail)) => {
+                Some(format!("Known failure{comment}").yellow())
+            }
+            (Verdict::Expected, Some(ExpectedOutcome::Flake)) => {
+                Some(format!("Known flake{comment}").yellow())
+            }
+            (Verdict::UnexpectedPass, _) => Some(format!("Unexpected pass{comment}").red().bold()),
+            // Failures without expectation are already obvious enough.
+            (Verdict::Expected, _) | (Verdict::UnexpectedFailure, None) => None,
+            (Verdict::UnexpectedFailure, Some(_)) => {
+                Some(format!("Unexpected failure{comment}").red().bold())
+            }
+        };
+
+        if verdict != Verdict::Expected {
+            self.unexpected_tests += 1;
+        }
+    }
+
+    fn end_suite(&mut self, duration: Duration) {
+        let _ = writeln!(
+            self.output,
             "\n{}",
             format!(
-                "Test Results: {}; {} passed; {} failed",
-                if self.failing_tests > 0 {
+                "Test Results: {}; {} passed; {} with warnings; {} failed; {} skipped; {} timed out; {} unexpected; finished in {:.2}s",
+                if self.unexpected_tests > 0 {
                     "failed".red()
                 } else {
                     "ok".green()
                 },
                 self.successful_tests,
-                self.failing_tests
+                self.warning_tests,
+                self.failing_tests,
+                self.skipped_tests,
+                self.timed_out_tests,
+                self.unexpected_tests,
+                duration.as_secs_f64()
             )
             .bold()
         );
@@ -66,14 +249,296 @@ impl TestResultWriter for ConsoleResultWriter {
         self.num_tests = 0;
         self.successful_tests = 0;
         self.failing_tests = 0;
+        self.skipped_tests = 0;
+        self.timed_out_tests = 0;
+        self.warning_tests = 0;
+        self.unexpected_tests = 0;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum OutputFormat {
+    #[default]
+    Console,
+    Igt,
+    Json,
+    Junit,
+    Tap,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Compare two runs written with --format json, and fail if the new one
+    /// has regressions
+    Compare {
+        /// Results of the reference run
+        old: PathBuf,
+
+        /// Results of the run to check
+        new: PathBuf,
+    },
+}
+
+/// Curated GPU Tests
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the DRM device to test
+    #[arg(long, conflicts_with_all = ["driver", "index", "bus_id", "by_path"])]
+    device: Option<PathBuf>,
+
+    /// Name of the DRM driver to test
+    #[arg(long, default_value = "vkms")]
+    driver: String,
+
+    /// Test the Nth device using the driver, starting at 0
+    #[arg(long, value_name = "N")]
+    index: Option<usize>,
+
+    /// Bus ID of the DRM device to test, such as 0000:03:00.0
+    #[arg(long, conflicts_with_all = ["driver", "index", "by_path"])]
+    bus_id: Option<String>,
+
+    /// Name of the DRM device to test in /dev/dri/by-path
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["driver", "index"])]
+    by_path: Option<String>,
+
+    /// Test the render node of the selected device
+    #[arg(long)]
+    render: bool,
+
+    /// Test every device using the driver, one after the other
+    #[arg(long, conflicts_with_all = ["device", "index", "bus_id", "by_path"])]
+    all_devices: bool,
+
+    /// List the selected tests and exit
+    #[arg(long)]
+    list: bool,
+
+    /// Only run tests whose full name matches the glob pattern
+    #[arg(long, value_name = "PATTERN")]
+    filter: Vec<Pattern>,
+
+    /// Skip tests whose full name matches the glob pattern
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<Pattern>,
+
+    /// Only run the test with this full name
+    #[arg(long, value_name = "NAME")]
+    test: Vec<String>,
+
+    /// Run the tests in a random order, using the given seed or a new one
+    #[allow(clippy::option_option)]
+    #[arg(long, value_name = "SEED")]
+    shuffle: Option<Option<u64>>,
+
+    /// Run each test in its own process
+    #[arg(long)]
+    isolate: bool,
+
+    /// Default timeout for each test, in seconds, 0 to disable it. A test
+    /// timing out aborts the run, unless it runs with --isolate and can be
+    /// killed.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+
+    /// Capture the kernel log of each test
+    #[arg(long)]
+    dmesg: bool,
+
+    /// How to report tests leaving warnings or errors in the kernel log:
+    /// ignore, warn or fail. Implies --dmesg if not ignore.
+    #[arg(long, value_name = "POLICY", default_value = "ignore")]
+    dmesg_policy: KernelLogPolicy,
+
+    /// Check whether a test tainted the kernel
+    #[arg(long)]
+    taint: bool,
+
+    /// Stop the run as soon as a test taints the kernel. Implies --taint.
+    #[arg(long)]
+    abort_on_taint: bool,
+
+    /// File listing the known issues of the driver
+    #[arg(long, value_name = "FILE")]
+    expectations: Option<PathBuf>,
+
+    /// Format of the test results
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// File to write the test results to, instead of the standard output
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+impl Args {
+    fn seed(&self) -> Option<u64> {
+        self.shuffle.map(|seed| {
+            seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs() ^ u64::from(d.subsec_nanos()))
+            })
+        })
+    }
+
+    fn device_specifier(&self) -> DeviceSpecifier {
+        let dev = if let Some(ref path) = self.device {
+            DeviceSpecifier::Path(path.clone())
+        } else if let Some(ref bus_id) = self.bus_id {
+            DeviceSpecifier::BusId(bus_id.clone())
+        } else if let Some(ref name) = self.by_path {
+            DeviceSpecifier::ByPath(name.clone())
+        } else if let Some(index) = self.index {
+            DeviceSpecifier::ModuleIndex(self.driver.clone(), index)
+        } else {
+            DeviceSpecifier::ModuleName(self.driver.clone())
+        };
+
+        if self.render {
+            DeviceSpecifier::RenderNode(Box::new(dev))
+        } else {
+            dev
+        }
+    }
+
+    fn selection(&self, seed: Option<u64>) -> TestSelection {
+        let mut selection = TestSelection::new();
+
+        for pattern in &self.filter {
+            selection = selection.include(pattern.clone());
+        }
+
+        for pattern in &self.exclude {
+            selection = selection.exclude(pattern.clone());
+        }
+
+        for name in &self.test {
+            selection = selection.name(name);
+        }
+
+        if let Some(seed) = seed {
+            selection = selection.shuffle(seed);
+        }
+
+        selection
+    }
+}
+
+fn compare(old: &Path, new: &Path) -> RunResult {
+    let read = |path: &Path| {
+        read_json_results(path).map_err(|e| eprintln!("Couldn't read {}: {e}", path.display()))
+    };
+
+    // Unreadable results aren't a regression, and scripts need to tell the
+    // two apart.
+    let (Ok(old), Ok(new)) = (read(old), read(new)) else {
+        return RunResult::Error;
+    };
+
+    let comparison = compare_results(&old, &new);
+
+    print!("{comparison}");
+
+    if comparison.is_regression() {
+        RunResult::Failure
+    } else {
+        RunResult::Success
     }
 }
 
 fn main() -> RunResult {
-    let mut writer = ConsoleResultWriter::new();
+    let args = Args::parse();
+
+    if let Some(Command::Compare { ref old, ref new }) = args.command {
+        return compare(old, new);
+    }
+
+    let seed = args.seed();
+
+    if let Some(seed) = seed {
+        eprintln!("Shuffling tests with seed {seed}, use --shuffle {seed} to replay this order");
+    }
+
+    let selection = args.selection(seed);
+
+    if args.list {
+        for test in list_tests(&selection) {
+            println!("{}", test.full_name());
+        }
+
+        return RunResult::Success;
+    }
+
+    let output: Box<dyn Write> = match args.output {
+        Some(ref path) => match File::create(path) {
+            Ok(f) => {
+                colored::control::set_override(false);
+                Box::new(f)
+            }
+            Err(e) => {
+                eprintln!("Couldn't create {}: {e}", path.display());
+                return RunResult::Error;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    let dev = args.device_specifier();
 
-    run_all(
-        &mut writer,
-        DeviceSpecifier::ModuleName(String::from("vkms")),
-    )
+    let expectations = match args.expectations {
+        Some(ref path) => match Expectations::load(path) {
+            Ok(expectations) => expectations,
+            Err(e) => {
+                eprintln!("Couldn't load the expectations {}: {e}", path.display());
+                return RunResult::Error;
+            }
+        },
+        None => Expectations::default(),
+    };
+
+    let options = RunOptions {
+        isolate: args.isolate,
+        timeout: (args.timeout > 0).then(|| Duration::from_secs(args.timeout)),
+        kernel_log: (args.dmesg || args.dmesg_policy != KernelLogPolicy::Ignore)
+            .then(|| PathBuf::from(DEFAULT_KMSG_PATH)),
+        kernel_log_policy: args.dmesg_policy,
+        taint: (args.taint || args.abort_on_taint).then(|| PathBuf::from(DEFAULT_TAINT_PATH)),
+        abort_on_taint: args.abort_on_taint,
+        expectations,
+        all_devices: args.all_devices,
+    };
+
+    match args.format {
+        OutputFormat::Console => {
+            let mut writer = ConsoleResultWriter::with_output(output);
+
+            run_all(&mut writer, dev, &selection, &options)
+        }
+        OutputFormat::Igt => {
+            let mut writer = IgtResultWriter::with_output(output);
+
+            run_all(&mut writer, dev, &selection, &options)
+        }
+        OutputFormat::Json => {
+            let mut writer = JsonResultWriter::with_output(output);
+
+            run_all(&mut writer, dev, &selection, &options)
+        }
+        OutputFormat::Junit => {
+            let mut writer = JUnitResultWriter::with_output(output);
+
+            run_all(&mut writer, dev, &selection, &options)
+        }
+        OutputFormat::Tap => {
+            let mut writer = TapResultWriter::with_output(output);
+
+            run_all(&mut writer, dev, &selection, &options)
+        }
+    }
 }
@@ -1,3 +1,4 @@
+#[allow(unused_imports)]
 mod prelude {
     pub use std::{
         fs::File,
@@ -8,7 +9,7 @@ mod prelude {
     pub use cgt_core::TestError;
     pub use cgt_macros::*;
     pub use drm_helpers::*;
-    pub use drm_uapi::{ClientCapability::*, *};
+    pub use drm_uapi::{ClientCapability::*, DriverCapability::*, *};
 }
 
 automod::dir!("src/tests");